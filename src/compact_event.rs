@@ -0,0 +1,173 @@
+//! A compact wire form of a batch of events.
+//!
+//! Within a self-contained, dependency-ordered batch, a parent that occurs earlier in the same
+//! batch is referenced by its position in the batch instead of its 32-byte hash. A parent outside
+//! the batch is referenced by its full hash. The bytes of a batch are produced by bincode with
+//! variable-length integer encoding, so small positions take a single byte.
+//!
+//! A compact batch is only valid as a whole: its positions are meaningless outside the batch.
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+use bincode::Options;
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+
+use crate::graph::{Action, Event, NodeId};
+use crate::hash::{Error as HashError, Hash, Hasher, Sha3Hasher};
+
+/// A compact batch error.
+#[derive(Debug, Fail)]
+pub enum Error {
+    /// A hasher error.
+    #[fail(display = "Hasher error: {}", _0)]
+    Hash(HashError),
+    /// A serialization error.
+    #[fail(display = "Serialization error: {}", _0)]
+    Serialize(bincode::Error),
+    /// A parent position that doesn't refer to an earlier event in the batch.
+    #[fail(display = "Invalid parent position: {}", _0)]
+    InvalidPosition(u64),
+}
+
+/// A reference to a parent of a compact event.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ParentRef {
+    /// The position of the parent in the batch.
+    Position(u64),
+    /// The hash of a parent outside the batch.
+    Hash(Hash),
+}
+
+/// An event with its parents referenced compactly.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompactEvent<N: NodeId> {
+    /// The ID of the creator of the event.
+    creator_id: N,
+    /// The reference to the self-parent event.
+    self_parent: Option<ParentRef>,
+    /// The reference to the other-parent event.
+    other_parent: Option<ParentRef>,
+    /// The Lamport timestamp of the event.
+    lamport: u64,
+    /// The event action.
+    action: Action<N>,
+    /// The signature of the event.
+    signature: Option<Vec<u8>>,
+}
+
+/// A dependency-ordered batch of compact events whose parents are hashed with `H`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompactBatch<N: NodeId, H: Hasher = Sha3Hasher>(
+    Vec<CompactEvent<N>>,
+    #[serde(skip)] PhantomData<H>,
+);
+
+impl<N: NodeId, H: Hasher> CompactBatch<N, H> {
+    /// Encodes a batch of events in which parents precede their children.
+    pub fn encode(events: &[Event<N>]) -> Result<Self, Error> {
+        let mut positions: BTreeMap<Hash, u64> = BTreeMap::new();
+        let mut compact = Vec::with_capacity(events.len());
+        for (position, event) in events.iter().enumerate() {
+            let parent_ref = |parent: Option<&Hash>| {
+                parent.map(|hash| match positions.get(hash) {
+                    Some(position) => ParentRef::Position(*position),
+                    None => ParentRef::Hash(hash.clone()),
+                })
+            };
+            compact.push(CompactEvent {
+                creator_id: event.creator_id().clone(),
+                self_parent: parent_ref(event.self_parent()),
+                other_parent: parent_ref(event.other_parent()),
+                lamport: event.lamport(),
+                action: event.action().clone(),
+                signature: event.signature().map(<[u8]>::to_vec),
+            });
+            let hash = event.hash_with::<H>().map_err(Error::Hash)?;
+            positions.insert(hash, position as u64);
+        }
+        Ok(CompactBatch(compact, PhantomData))
+    }
+
+    /// Decodes the batch back into full events.
+    pub fn decode(self) -> Result<Vec<Event<N>>, Error> {
+        let mut hashes: Vec<Hash> = Vec::with_capacity(self.0.len());
+        let mut events = Vec::with_capacity(self.0.len());
+        for compact in self.0 {
+            let parent_hash = |parent: Option<ParentRef>| match parent {
+                None => Ok(None),
+                Some(ParentRef::Hash(hash)) => Ok(Some(hash)),
+                Some(ParentRef::Position(position)) => hashes
+                    .get(position as usize)
+                    .cloned()
+                    .map(Some)
+                    .ok_or(Error::InvalidPosition(position)),
+            };
+            let mut event = Event::new(
+                compact.creator_id,
+                parent_hash(compact.self_parent)?,
+                parent_hash(compact.other_parent)?,
+                compact.lamport,
+                compact.action,
+            );
+            if let Some(signature) = compact.signature {
+                event = event.with_signature(signature);
+            }
+            hashes.push(event.hash_with::<H>().map_err(Error::Hash)?);
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    /// Serializes the batch using variable-length integer encoding.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        bincode::DefaultOptions::new()
+            .serialize(self)
+            .map_err(Error::Serialize)
+    }
+}
+
+impl<N, H> CompactBatch<N, H>
+where
+    N: NodeId + for<'de> Deserialize<'de>,
+    H: Hasher,
+{
+    /// Deserializes a batch serialized with `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        bincode::DefaultOptions::new()
+            .deserialize(bytes)
+            .map_err(Error::Serialize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::CompactBatch;
+    use crate::graph::{Action, Event};
+    use crate::hash::{compute_hash, Hash};
+
+    #[test]
+    fn compact_batch_round_trip() {
+        let outside: Hash = compute_hash(&"outside").unwrap();
+        let a0 = Event::new(0, Some(outside), None, 1, Action::Init(BTreeSet::new()));
+        let a0_hash = a0.hash().unwrap();
+        let b0 = Event::new(1, None, Some(a0_hash.clone()), 2, Action::Add(2));
+        let b0_hash = b0.hash().unwrap();
+        let a1 = Event::new(0, Some(a0_hash), Some(b0_hash), 3, Action::Remove(1))
+            .with_signature(vec![1, 2, 3]);
+        let events = vec![a0, b0, a1];
+        let bytes = CompactBatch::<u64>::encode(&events)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        assert!(bytes.len() < bincode::serialize(&events).unwrap().len());
+        let decoded = CompactBatch::<u64>::from_bytes(&bytes)
+            .unwrap()
+            .decode()
+            .unwrap();
+        assert_eq!(decoded, events);
+    }
+}
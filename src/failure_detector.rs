@@ -5,10 +5,30 @@
 //! It is local because in order to find node failures it only uses the knowledge of the node on
 //! which it is running.
 
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
 use failure::Fail;
+use log::warn;
 
 use crate::graph::NodeId;
 
+/// A source of the current time for failure detectors.
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// The system clock.
+#[derive(Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
 /// A failure detector error.
 #[derive(Debug, Fail)]
 pub enum Error {
@@ -17,6 +37,17 @@ pub enum Error {
     Poll,
 }
 
+/// The liveness status of a node as seen by a failure detector.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeStatus {
+    /// The node has shown activity recently.
+    Alive,
+    /// The node has been silent for too long but may still recover.
+    Suspect,
+    /// The node has been suspected for too long and is considered failed.
+    Confirmed,
+}
+
 /// A tentative interface to a node failure detector.
 pub trait FailureDetector<N: NodeId> {
     /// Finds any new failures and appends those to the failure queue.
@@ -25,30 +56,272 @@ pub trait FailureDetector<N: NodeId> {
     /// Takes any unhandled node failures for processing and removes those from the queue of
     /// unhandled failures.
     fn dequeue_failures(&mut self) -> Vec<N>;
+
+    /// Returns the status of a node, if the detector tracks it.
+    fn status(&self, _node: &N) -> Option<NodeStatus> {
+        None
+    }
+
+    /// Reports that a node has been heard from, for example through a gossiped event.
+    fn record_activity(&mut self, _node: &N) {}
+}
+
+/// A typical time of silence after which a node becomes suspect, to be set with
+/// `set_activity_timeout`.
+pub const DEFAULT_ACTIVITY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A typical time after which a suspect node is confirmed failed, to be set with
+/// `set_suspect_timeout`.
+pub const DEFAULT_SUSPECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The default maximum number of unhandled failures kept in the queue.
+pub const DEFAULT_MAX_FAILURES: usize = 1024;
+
+/// The liveness record of a tracked node.
+struct Liveness {
+    /// The current status.
+    status: NodeStatus,
+    /// The time of the last status change or, for an alive node, of its last activity.
+    since: Instant,
 }
 
-/// The internal node failure detector.
-pub struct InternalFailureDetector<N: NodeId> {
-    /// The queue of unhandled node failures.
+/// The internal node failure detector. Nodes move from `Alive` to `Suspect` after a period of
+/// silence and from `Suspect` to `Confirmed` after a further `suspect_timeout`. Only confirmed
+/// nodes are returned as failures.
+///
+/// Both timeouts are unset by default, so that a new detector never reports failures. The timeouts
+/// should only be set if every tracked node is heard from regularly.
+pub struct InternalFailureDetector<N: NodeId, C: Clock = SystemClock> {
+    /// The liveness records of the tracked nodes.
+    nodes: BTreeMap<N, Liveness>,
+    /// The time of silence after which a node becomes suspect, if set.
+    activity_timeout: Option<Duration>,
+    /// The time after which a suspect node is confirmed failed, if set.
+    suspect_timeout: Option<Duration>,
+    /// The queue of unhandled node failures, the oldest first. Each node appears at most once.
     failures: Vec<N>,
+    /// The maximum number of unhandled failures kept in the queue.
+    max_failures: usize,
+    /// The source of the current time.
+    clock: C,
 }
 
-impl<N: NodeId> FailureDetector<N> for InternalFailureDetector<N> {
+impl<N: NodeId, C: Clock> FailureDetector<N> for InternalFailureDetector<N, C> {
     fn poll_failures(&mut self) -> Result<(), Error> {
-        // TODO
-        Err(Error::Poll)
+        let now = self.clock.now();
+        let mut confirmed = Vec::new();
+        for (node, liveness) in self.nodes.iter_mut() {
+            let elapsed = now.saturating_duration_since(liveness.since);
+            match liveness.status {
+                NodeStatus::Alive if self.activity_timeout.is_some_and(|t| elapsed >= t) => {
+                    liveness.status = NodeStatus::Suspect;
+                    liveness.since = now;
+                }
+                NodeStatus::Suspect if self.suspect_timeout.is_some_and(|t| elapsed >= t) => {
+                    liveness.status = NodeStatus::Confirmed;
+                    liveness.since = now;
+                    confirmed.push(node.clone());
+                }
+                _ => {}
+            }
+        }
+        for node in confirmed {
+            self.push_failure(node);
+        }
+        Ok(())
     }
 
     fn dequeue_failures(&mut self) -> Vec<N> {
         self.failures.drain(..).collect()
     }
+
+    fn status(&self, node: &N) -> Option<NodeStatus> {
+        self.nodes.get(node).map(|liveness| liveness.status)
+    }
+
+    /// Records activity of a node, starting to track it if necessary. A suspect node becomes
+    /// alive again. A confirmed failure is final.
+    fn record_activity(&mut self, node: &N) {
+        let now = self.clock.now();
+        let liveness = self.nodes.entry(node.clone()).or_insert(Liveness {
+            status: NodeStatus::Alive,
+            since: now,
+        });
+        if liveness.status != NodeStatus::Confirmed {
+            liveness.status = NodeStatus::Alive;
+            liveness.since = now;
+        }
+    }
+}
+
+impl<N: NodeId> Default for InternalFailureDetector<N> {
+    fn default() -> Self {
+        InternalFailureDetector::with_clock(SystemClock)
+    }
 }
 
 impl<N: NodeId> InternalFailureDetector<N> {
     /// Creates a new failure detector.
     pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<N: NodeId, C: Clock> InternalFailureDetector<N, C> {
+    /// Creates a new failure detector that takes the current time from the given clock.
+    pub fn with_clock(clock: C) -> Self {
         InternalFailureDetector {
+            nodes: BTreeMap::new(),
+            activity_timeout: None,
+            suspect_timeout: None,
             failures: Vec::new(),
+            max_failures: DEFAULT_MAX_FAILURES,
+            clock,
+        }
+    }
+
+    /// Sets the time of silence after which a node becomes suspect.
+    pub fn set_activity_timeout(&mut self, activity_timeout: Duration) {
+        self.activity_timeout = Some(activity_timeout);
+    }
+
+    /// Sets the time after which a suspect node is confirmed failed.
+    pub fn set_suspect_timeout(&mut self, suspect_timeout: Duration) {
+        self.suspect_timeout = Some(suspect_timeout);
+    }
+
+    /// Sets the maximum number of unhandled failures kept in the queue. Beyond that, the oldest
+    /// failures are dropped.
+    pub fn set_max_failures(&mut self, max_failures: usize) {
+        self.max_failures = max_failures;
+    }
+
+    /// Appends a failure to the queue unless the node is already queued, dropping the oldest
+    /// failures if the queue is full.
+    fn push_failure(&mut self, node: N) {
+        if self.failures.contains(&node) {
+            return;
+        }
+        self.failures.push(node);
+        if self.failures.len() > self.max_failures {
+            let excess = self.failures.len() - self.max_failures;
+            for dropped in self.failures.drain(..excess) {
+                warn!("Failure queue full, dropping the failure of {:?}", dropped);
+            }
+        }
+    }
+}
+
+/// A clock that is advanced manually, for tests.
+#[cfg(test)]
+#[derive(Clone)]
+pub(crate) struct ManualClock(std::rc::Rc<std::cell::Cell<Instant>>);
+
+#[cfg(test)]
+impl ManualClock {
+    /// Creates a clock that shows the current time until advanced.
+    pub(crate) fn new() -> Self {
+        ManualClock(std::rc::Rc::new(std::cell::Cell::new(Instant::now())))
+    }
+
+    /// Advances the clock.
+    pub(crate) fn advance(&self, duration: Duration) {
+        self.0.set(self.0.get() + duration);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FailureDetector, InternalFailureDetector, ManualClock, NodeStatus};
+
+    fn detector(clock: &ManualClock) -> InternalFailureDetector<u64, ManualClock> {
+        let mut detector = InternalFailureDetector::with_clock(clock.clone());
+        detector.set_activity_timeout(Duration::from_secs(1));
+        detector.set_suspect_timeout(Duration::from_secs(2));
+        detector
+    }
+
+    #[test]
+    fn detector_without_timeouts_reports_no_failures() {
+        let clock = ManualClock::new();
+        let mut detector = InternalFailureDetector::with_clock(clock.clone());
+        detector.record_activity(&1);
+        clock.advance(Duration::from_secs(3600));
+        detector.poll_failures().unwrap();
+        assert_eq!(detector.status(&1), Some(NodeStatus::Alive));
+        assert!(detector.dequeue_failures().is_empty());
+    }
+
+    #[test]
+    fn suspect_node_recovers() {
+        let clock = ManualClock::new();
+        let mut detector = detector(&clock);
+        detector.record_activity(&1);
+        assert_eq!(detector.status(&1), Some(NodeStatus::Alive));
+        clock.advance(Duration::from_secs(1));
+        detector.poll_failures().unwrap();
+        assert_eq!(detector.status(&1), Some(NodeStatus::Suspect));
+        detector.record_activity(&1);
+        assert_eq!(detector.status(&1), Some(NodeStatus::Alive));
+        clock.advance(Duration::from_secs(2));
+        detector.poll_failures().unwrap();
+        assert_eq!(detector.status(&1), Some(NodeStatus::Suspect));
+        assert!(detector.dequeue_failures().is_empty());
+        assert_eq!(detector.status(&2), None);
+    }
+
+    #[test]
+    fn suspect_node_is_confirmed_after_timeout() {
+        let clock = ManualClock::new();
+        let mut detector = detector(&clock);
+        detector.record_activity(&1);
+        clock.advance(Duration::from_secs(1));
+        detector.poll_failures().unwrap();
+        clock.advance(Duration::from_secs(1));
+        detector.poll_failures().unwrap();
+        assert_eq!(detector.status(&1), Some(NodeStatus::Suspect));
+        assert!(detector.dequeue_failures().is_empty());
+        clock.advance(Duration::from_secs(1));
+        detector.poll_failures().unwrap();
+        assert_eq!(detector.status(&1), Some(NodeStatus::Confirmed));
+        assert_eq!(detector.dequeue_failures(), vec![1]);
+        // A confirmed failure is reported once and is final.
+        detector.record_activity(&1);
+        detector.poll_failures().unwrap();
+        assert_eq!(detector.status(&1), Some(NodeStatus::Confirmed));
+        assert!(detector.dequeue_failures().is_empty());
+    }
+
+    #[test]
+    fn failure_queue_is_deduplicated_and_bounded() {
+        let clock = ManualClock::new();
+        let mut detector = detector(&clock);
+        let confirm = |detector: &mut InternalFailureDetector<u64, ManualClock>| {
+            clock.advance(Duration::from_secs(1));
+            detector.poll_failures().unwrap();
+            clock.advance(Duration::from_secs(2));
+            detector.poll_failures().unwrap();
+        };
+        detector.record_activity(&1);
+        confirm(&mut detector);
+        // Further polls don't queue the failure again.
+        confirm(&mut detector);
+        assert_eq!(detector.dequeue_failures(), vec![1]);
+        detector.set_max_failures(2);
+        for node in 2..6 {
+            detector.record_activity(&node);
         }
+        confirm(&mut detector);
+        assert_eq!(detector.dequeue_failures(), vec![4, 5]);
+        assert!(detector.dequeue_failures().is_empty());
     }
 }
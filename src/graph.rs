@@ -3,18 +3,20 @@ use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fmt::Debug;
 use std::iter;
+use std::marker::PhantomData;
 
 use failure::Fail;
-use serde::{Deserialize, Serialize};
+use serde::de::Error as DeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::hash::{compute_hash, Error as HashError, Hash};
+use crate::hash::{compute_hash_with, Error as HashError, Hash, Hasher, Sha3Hasher};
 
 /// A peer node's unique identifier.
-pub trait NodeId: Eq + Ord + Clone + Debug + Send + Serialize + Sync {}
-impl<N> NodeId for N where N: Eq + Ord + Clone + Debug + Send + Serialize + Sync {}
+pub trait NodeId: Eq + Ord + Clone + Debug + Send + Serialize + Sync + 'static {}
+impl<N> NodeId for N where N: Eq + Ord + Clone + Debug + Send + Serialize + Sync + 'static {}
 
 /// Group membership actions.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Action<N: NodeId> {
     /// Register the initial group.
     Init(BTreeSet<N>),
@@ -25,7 +27,7 @@ pub enum Action<N: NodeId> {
 }
 
 /// A gossip event.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Event<N: NodeId> {
     /// The ID of the creator of the event.
     creator_id: N,
@@ -33,11 +35,41 @@ pub struct Event<N: NodeId> {
     self_parent: Option<Hash>,
     /// The hash of the other-parent event.
     other_parent: Option<Hash>,
+    /// The Lamport timestamp: one more than the greatest timestamp of the parents, or 0 for an
+    /// event without parents.
+    lamport: u64,
     /// The event action.
     action: Action<N>,
+    /// The signature of the creator over the other fields.
+    signature: Option<Vec<u8>>,
 }
 
 impl<N: NodeId> Event<N> {
+    /// Constructs a new event. The Lamport timestamp of an event to be inserted into a graph is
+    /// given by `Graph::next_lamport`.
+    pub fn new(
+        creator_id: N,
+        self_parent: Option<Hash>,
+        other_parent: Option<Hash>,
+        lamport: u64,
+        action: Action<N>,
+    ) -> Self {
+        Event {
+            creator_id,
+            self_parent,
+            other_parent,
+            lamport,
+            action,
+            signature: None,
+        }
+    }
+
+    /// Attaches a signature to the event.
+    pub fn with_signature(mut self, signature: Vec<u8>) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
     /// The ID of the creator of the event.
     pub fn creator_id(&self) -> &N {
         &self.creator_id
@@ -53,10 +85,74 @@ impl<N: NodeId> Event<N> {
         self.other_parent.as_ref()
     }
 
+    /// The Lamport timestamp of the event.
+    pub fn lamport(&self) -> u64 {
+        self.lamport
+    }
+
     /// The event action.
     pub fn action(&self) -> &Action<N> {
         &self.action
     }
+
+    /// The signature of the event, if any.
+    pub fn signature(&self) -> Option<&[u8]> {
+        self.signature.as_deref()
+    }
+
+    /// The bytes covered by the signature: the serialization of all fields but the signature.
+    pub fn signed_bytes(&self) -> Result<Vec<u8>, bincode::Error> {
+        bincode::serialize(&self.signed_fields())
+    }
+
+    /// Computes the hash that identifies the event, using the default hashing algorithm.
+    pub fn hash(&self) -> Result<Hash, HashError> {
+        self.hash_with::<Sha3Hasher>()
+    }
+
+    /// Computes the hash that identifies the event, using `H`. The hash covers the same fields as
+    /// the signature, so copies of an event with different signatures have the same identity.
+    pub fn hash_with<H: Hasher>(&self) -> Result<Hash, HashError> {
+        compute_hash_with::<_, H>(&self.signed_fields())
+    }
+
+    /// Gets all fields but the signature.
+    #[allow(clippy::type_complexity)]
+    fn signed_fields(&self) -> (&N, &Option<Hash>, &Option<Hash>, u64, &Action<N>) {
+        (
+            &self.creator_id,
+            &self.self_parent,
+            &self.other_parent,
+            self.lamport,
+            &self.action,
+        )
+    }
+}
+
+/// The action of a pruned event, retained with the key of the event in the topological order.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PrunedAction<N: NodeId> {
+    /// The Lamport timestamp of the event.
+    lamport: u64,
+    /// The ID of the creator of the event.
+    creator_id: N,
+    /// The hash of the event.
+    hash: Hash,
+    /// The event action.
+    action: Action<N>,
+}
+
+/// A reference to the action of an event in the graph or of a pruned event.
+#[derive(Clone, Debug)]
+pub struct ActionRef<'a, N: NodeId + 'a> {
+    /// The ID of the creator of the event.
+    pub creator_id: &'a N,
+    /// The hash of the event.
+    pub hash: &'a Hash,
+    /// The Lamport timestamp of the event.
+    pub lamport: u64,
+    /// The event action.
+    pub action: &'a Action<N>,
 }
 
 /// A reference to an `Event`, and its index in the gossip graph.
@@ -76,7 +172,7 @@ impl<'a, N: NodeId> Eq for EventRef<'a, N> {}
 
 impl<'a, N: NodeId> PartialOrd for EventRef<'a, N> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.index.partial_cmp(&other.index)
+        Some(self.cmp(other))
     }
 }
 
@@ -89,7 +185,7 @@ impl<'a, N: NodeId> Ord for EventRef<'a, N> {
 impl<'a, N: NodeId + 'a> EventRef<'a, N> {
     /// The ID of the creator of the event.
     pub fn creator_id(&self) -> &N {
-        &self.event.creator_id()
+        self.event.creator_id()
     }
 
     /// The hash of the self-parent of the event.
@@ -102,6 +198,11 @@ impl<'a, N: NodeId + 'a> EventRef<'a, N> {
         self.event.other_parent.as_ref()
     }
 
+    /// The Lamport timestamp of the event.
+    pub fn lamport(&self) -> u64 {
+        self.event.lamport
+    }
+
     /// The event action.
     pub fn action(&self) -> &Action<N> {
         &self.event.action
@@ -110,45 +211,201 @@ impl<'a, N: NodeId + 'a> EventRef<'a, N> {
 
 /// A gossip graph error.
 #[derive(Debug, Fail)]
-pub enum Error {
+pub enum Error<N: NodeId> {
     /// A hasher error.
     #[fail(display = "Hasher error: {}", _0)]
     Hash(HashError),
+    /// A creator produced two different events with the same self-parent.
+    #[fail(display = "Fork by {:?}: {:?}", creator, hashes)]
+    Fork {
+        /// The creator of the forking events.
+        creator: N,
+        /// The hashes of the forking events, the inserted one last.
+        hashes: Vec<Hash>,
+    },
+    /// A different event with the same hash is already in the graph.
+    #[fail(display = "Hash collision: {}", hash)]
+    HashCollision {
+        /// The colliding hash.
+        hash: Hash,
+    },
+    /// A parent of the inserted event is not in the graph.
+    #[fail(display = "Missing parent: {}", hash)]
+    MissingParent {
+        /// The hash of the missing parent.
+        hash: Hash,
+    },
+    /// The event is not in the graph.
+    #[fail(display = "Unknown event: {}", hash)]
+    UnknownEvent {
+        /// The hash of the event.
+        hash: Hash,
+    },
+    /// An event whose parent is in neither of the merged graphs.
+    #[fail(display = "Orphan event {} with missing parent {}", hash, parent)]
+    Orphan {
+        /// The hash of the orphan event.
+        hash: Hash,
+        /// The hash of the missing parent.
+        parent: Hash,
+    },
+    /// A graph serialization error.
+    #[fail(display = "Serialization error: {}", _0)]
+    Serialize(bincode::Error),
+    /// A snapshot in an unsupported format, with its version if it has one.
+    #[fail(display = "Unsupported snapshot version: {:?}", _0)]
+    SnapshotVersion(Option<u32>),
 }
 
-/// A gossip graph.
+/// The tag that starts every graph snapshot.
+const SNAPSHOT_MAGIC: [u8; 4] = *b"LNMG";
+
+/// The version of the snapshot format, which follows the tag. Snapshots from before versioning
+/// have no tag. Version 2 added the Lamport timestamps and signatures of events and the actions of
+/// pruned events.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// A gossip graph whose events are hashed with `H`.
 #[derive(Clone, Debug)]
-pub struct Graph<N>
+pub struct Graph<N, H = Sha3Hasher>
 where
     N: NodeId,
+    H: Hasher,
 {
     /// All events in the graph.
     events: Vec<Event<N>>,
     /// A mapping of event hashes to indices of the corresponding events in `events`.
     indices: BTreeMap<Hash, usize>,
+    /// The hashes of the events in `events`.
+    hashes: Vec<Hash>,
+    /// A mapping of creators to indices of their latest events in `events`.
+    latest: BTreeMap<N, usize>,
+    /// A mapping of creators to indices of the heads of their self-parent chains in `events`.
+    heads: BTreeMap<N, BTreeSet<usize>>,
+    /// A mapping of Lamport timestamps, creators and hashes to indices of the corresponding events
+    /// in `events`, in the topological order.
+    order: BTreeMap<(u64, N, Hash), usize>,
+    /// A mapping of event hashes to indices of the children of those events in `events`. The
+    /// parent events need not be in the graph.
+    children: BTreeMap<Hash, Vec<usize>>,
+    /// The actions of pruned events in the topological order.
+    pruned: Vec<PrunedAction<N>>,
+    /// The number of events by each creator that precede the events in the graph: the pruned
+    /// events and the events summarized by the base summary.
+    offsets: BTreeMap<N, u64>,
+    /// The hashing algorithm.
+    hasher: PhantomData<H>,
 }
 
-impl<N> Default for Graph<N>
+impl<N, H> Default for Graph<N, H>
 where
     N: NodeId,
+    H: Hasher,
 {
     fn default() -> Self {
         Self {
             events: Vec::new(),
             indices: BTreeMap::new(),
+            hashes: Vec::new(),
+            latest: BTreeMap::new(),
+            heads: BTreeMap::new(),
+            order: BTreeMap::new(),
+            children: BTreeMap::new(),
+            pruned: Vec::new(),
+            offsets: BTreeMap::new(),
+            hasher: PhantomData,
+        }
+    }
+}
+
+/// A graph is serialized as the actions of its pruned events followed by the sequence of its
+/// events in the canonical order, which depends only on the set of events and not on the order in
+/// which they were inserted. The `indices` are not serialized but reconstructed on
+/// deserialization. Hence the serialized bytes of two graphs with the same events are identical.
+impl<N, H> Serialize for Graph<N, H>
+where
+    N: NodeId,
+    H: Hasher,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let events: Vec<&Event<N>> = self
+            .canonical_indices()
+            .into_iter()
+            .map(|index| &self.events[index])
+            .collect();
+        (&self.pruned, events).serialize(serializer)
+    }
+}
+
+impl<'de, N, H> Deserialize<'de> for Graph<N, H>
+where
+    N: NodeId + Deserialize<'de>,
+    H: Hasher,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (pruned, events) = <(Vec<PrunedAction<N>>, Vec<Event<N>>)>::deserialize(deserializer)?;
+        let mut graph = Graph::new();
+        for action in &pruned {
+            *graph.offsets.entry(action.creator_id.clone()).or_insert(0) += 1;
+        }
+        graph.pruned = pruned;
+        for event in events {
+            graph.insert(event).map_err(D::Error::custom)?;
+        }
+        Ok(graph)
+    }
+}
+
+impl<N, H> Graph<N, H>
+where
+    N: NodeId + for<'de> Deserialize<'de>,
+    H: Hasher,
+{
+    /// Restores a graph from a snapshot taken with `to_bytes`. Every event is hashed anew, so the
+    /// lookups of the restored graph don't depend on any index data in the snapshot. A snapshot
+    /// of another format version is rejected.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error<N>> {
+        let (magic, version): ([u8; 4], u32) =
+            bincode::deserialize(bytes).map_err(|_| Error::SnapshotVersion(None))?;
+        if magic != SNAPSHOT_MAGIC {
+            return Err(Error::SnapshotVersion(None));
         }
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::SnapshotVersion(Some(version)));
+        }
+        let (_, _, graph): ([u8; 4], u32, Self) =
+            bincode::deserialize(bytes).map_err(Error::Serialize)?;
+        Ok(graph)
     }
 }
 
-impl<N> Graph<N>
+impl<N, H> Graph<N, H>
 where
     N: NodeId,
+    H: Hasher,
 {
     /// Constructs a new, empty gossip graph.
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Constructs a new, empty gossip graph that continues from events outside the graph, such as
+    /// the events before a checkpoint. The base summary holds the number of those events by each
+    /// creator, and counts towards sequence numbers and the summary of the graph. It is not part
+    /// of snapshots.
+    pub fn with_base_summary(base_summary: BTreeMap<N, u64>) -> Self {
+        Graph {
+            offsets: base_summary,
+            ..Self::default()
+        }
+    }
+
+    /// Takes a snapshot of the graph: the serialized graph preceded by the snapshot format
+    /// version.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error<N>> {
+        bincode::serialize(&(SNAPSHOT_MAGIC, SNAPSHOT_VERSION, self)).map_err(Error::Serialize)
+    }
+
     /// Gets the index of an event with the given hash.
     pub fn get_index(&self, hash: &Hash) -> Option<usize> {
         self.indices.get(hash).cloned()
@@ -159,17 +416,49 @@ where
         self.indices.contains_key(hash)
     }
 
+    /// Gets all creators that have contributed events to the graph.
+    ///
+    /// These are all historical creators, including those that are not current group members.
+    pub fn creators(&self) -> BTreeSet<N> {
+        self.latest.keys().cloned().collect()
+    }
+
+    /// Gets the number of creators that have contributed events to the graph.
+    ///
+    /// This counts all historical creators, including those that are not current group members.
+    pub fn creator_count(&self) -> usize {
+        self.latest.len()
+    }
+
     /// Inserts a new event into the graph.
     ///
-    /// FIXME: handle hash collisions.
-    pub fn insert(&mut self, event: Event<N>) -> Result<EventRef<N>, Error> {
-        let hash = compute_hash(&event).map_err(Error::Hash)?;
-        let index = match self.indices.entry(hash) {
-            Entry::Occupied(entry) => *entry.get(),
+    /// Inserting an event that is already in the graph has no effect, even if its signature
+    /// differs, and the signature of the event in the graph is kept. If a different event with the
+    /// same hash is in the graph, an error is returned.
+    pub fn insert(&mut self, event: Event<N>) -> Result<EventRef<'_, N>, Error<N>> {
+        let hash = event.hash_with::<H>().map_err(Error::Hash)?;
+        let index = match self.indices.entry(hash.clone()) {
+            Entry::Occupied(entry) => {
+                let index = *entry.get();
+                if self.events[index].signed_fields() != event.signed_fields() {
+                    return Err(Error::HashCollision { hash });
+                }
+                index
+            }
             Entry::Vacant(entry) => {
                 let index = self.events.len();
+                self.latest.insert(event.creator_id().clone(), index);
+                self.order.insert(
+                    (event.lamport(), event.creator_id().clone(), hash.clone()),
+                    index,
+                );
+                for parent in event.self_parent().into_iter().chain(event.other_parent()) {
+                    self.children.entry(parent.clone()).or_default().push(index);
+                }
                 self.events.push(event);
+                self.hashes.push(hash);
                 entry.insert(index);
+                self.update_heads(index);
                 index
             }
         };
@@ -179,8 +468,115 @@ where
         })
     }
 
+    /// Updates the chain heads of the creator of a newly inserted event. The event is a head
+    /// unless a child by the same creator was inserted before it, and it replaces its self-parent
+    /// as a head.
+    fn update_heads(&mut self, index: usize) {
+        let event = &self.events[index];
+        let hash = &self.hashes[index];
+        let has_self_child = self.child_indices(index).iter().any(|&child| {
+            let child = &self.events[child];
+            child.self_parent() == Some(hash) && child.creator_id() == event.creator_id()
+        });
+        let self_parent = event
+            .self_parent()
+            .and_then(|hash| self.get_index(hash))
+            .filter(|&parent| self.events[parent].creator_id() == event.creator_id());
+        let heads = self.heads.entry(event.creator_id().clone()).or_default();
+        if !has_self_child {
+            heads.insert(index);
+        }
+        if let Some(parent) = self_parent {
+            heads.remove(&parent);
+        }
+    }
+
+    /// Inserts a new event into the graph unless one of its parents is missing, so that every
+    /// edge of the graph can be resolved. Events without parents are always accepted.
+    pub fn insert_validated(&mut self, event: Event<N>) -> Result<EventRef<'_, N>, Error<N>> {
+        if let Some(hash) = event
+            .self_parent()
+            .into_iter()
+            .chain(event.other_parent())
+            .find(|hash| !self.contains(hash))
+        {
+            return Err(Error::MissingParent { hash: hash.clone() });
+        }
+        self.insert(event)
+    }
+
+    /// Inserts a new event into the graph unless it forks the chain of its creator.
+    ///
+    /// A fork is a pair of different events by the same creator that have the same self-parent.
+    /// Events that merely share an other-parent are not forks.
+    pub fn insert_checked(&mut self, event: Event<N>) -> Result<EventRef<'_, N>, Error<N>> {
+        if let Some(self_parent) = event.self_parent() {
+            let hash = event.hash_with::<H>().map_err(Error::Hash)?;
+            let mut hashes: Vec<Hash> = self
+                .children
+                .get(self_parent)
+                .into_iter()
+                .flatten()
+                .filter(|&&index| {
+                    let other = &self.events[index];
+                    other.self_parent() == Some(self_parent)
+                        && other.creator_id() == event.creator_id()
+                        && self.hashes[index] != hash
+                })
+                .map(|&index| self.hashes[index].clone())
+                .collect();
+            if !hashes.is_empty() {
+                hashes.push(hash);
+                return Err(Error::Fork {
+                    creator: event.creator_id().clone(),
+                    hashes,
+                });
+            }
+        }
+        self.insert(event)
+    }
+
+    /// Inserts all events of `other` that are not in this graph, parents before children, and
+    /// returns the hashes of the inserted events in the order of insertion.
+    ///
+    /// If an event to be inserted has a parent that is in neither graph, an error is returned and
+    /// nothing is inserted.
+    pub fn merge(&mut self, other: &Graph<N, H>) -> Result<Vec<Hash>, Error<N>> {
+        let new: Vec<usize> = other
+            .canonical_indices()
+            .into_iter()
+            .filter(|&index| !self.contains(&other.hashes[index]))
+            .collect();
+        for &index in &new {
+            let event = &other.events[index];
+            let missing = event
+                .self_parent()
+                .into_iter()
+                .chain(event.other_parent())
+                .find(|parent| !self.contains(parent) && !other.contains(parent));
+            if let Some(parent) = missing {
+                return Err(Error::Orphan {
+                    hash: other.hashes[index].clone(),
+                    parent: parent.clone(),
+                });
+            }
+        }
+        for &index in &new {
+            self.insert(other.events[index].clone())?;
+        }
+        Ok(new
+            .into_iter()
+            .map(|index| other.hashes[index].clone())
+            .collect())
+    }
+
+    /// Gets the hash of the event with a given index, if it exists.
+    pub fn get_hash(&self, index: usize) -> Option<&Hash> {
+        self.hashes.get(index)
+    }
+
     /// Gets the event with a given index, if it exists.
-    pub fn get_by_index(&self, index: usize) -> Option<EventRef<N>> {
+    pub fn get_by_index(&self, index: usize) -> Option<EventRef<'_, N>> {
         self.events
             .get(index)
             .map(|event| EventRef { event, index })
@@ -192,35 +588,1343 @@ where
             .and_then(|index| self.get_by_index(index))
     }
 
+    /// Gets the self-parent and the other-parent of an event.
+    ///
+    /// A parent is `None` if the event has no such parent or if the parent hash is present but the
+    /// parent event is missing from the graph.
+    pub fn parents<'a>(
+        &'a self,
+        event: EventRef<'a, N>,
+    ) -> (Option<EventRef<'a, N>>, Option<EventRef<'a, N>>) {
+        let get = |hash: Option<&Hash>| hash.and_then(|hash| self.get_by_hash(hash));
+        (get(event.self_parent()), get(event.other_parent()))
+    }
+
     /// Gets all the ancestors of an event in the graph.
-    pub fn ancestors<'a>(&'a self, event: EventRef<'a, N>) -> AncestorIter<'a, N> {
+    pub fn ancestors<'a>(&'a self, event: EventRef<'a, N>) -> AncestorIter<'a, N, H> {
         AncestorIter {
             graph: self,
-            queue: iter::once(event).collect(),
+            visited: iter::once(event.index).collect(),
+            queue: iter::once((event, 0)).collect(),
+            max_depth: None,
+        }
+    }
+
+    /// Iterates over the ancestors of `event` that are at most `max_depth` generations away from
+    /// it. Depth 0 yields only `event` itself.
+    pub fn ancestors_within<'a>(
+        &'a self,
+        event: EventRef<'a, N>,
+        max_depth: usize,
+    ) -> AncestorIter<'a, N, H> {
+        AncestorIter {
+            max_depth: Some(max_depth),
+            ..self.ancestors(event)
+        }
+    }
+
+    /// Gets all the descendants of an event in the graph, excluding the event itself.
+    pub fn descendants<'a>(&'a self, event: EventRef<'a, N>) -> DescendantIter<'a, N, H> {
+        let mut iter = DescendantIter {
+            graph: self,
+            queue: VecDeque::new(),
+            visited: BTreeSet::new(),
+        };
+        iter.visited.insert(event.index);
+        iter.push_children(event.index);
+        iter
+    }
+
+    /// Gets the indices of the children of the event with the given index.
+    fn child_indices(&self, index: usize) -> &[usize] {
+        self.children
+            .get(&self.hashes[index])
+            .map_or(&[], |children| children.as_slice())
+    }
+
+    /// Gets the sequence number of an event, which is the number of its self-ancestors in the
+    /// graph plus the number of pruned or base events by its creator.
+    pub fn sequence(&self, event: &EventRef<N>) -> u64 {
+        let mut sequence = self.offsets.get(event.creator_id()).cloned().unwrap_or(0);
+        let mut current = event.self_parent().and_then(|hash| self.get_by_hash(hash));
+        while let Some(self_parent) = current {
+            sequence += 1;
+            current = self_parent
+                .self_parent()
+                .and_then(|hash| self.get_by_hash(hash));
+        }
+        sequence
+    }
+
+    /// Gets the events that a peer needs in order to become current.
+    ///
+    /// The peer summary maps creators to the number of events by each creator that the peer
+    /// holds, that is, the peer holds the events of that creator with a lower sequence number. The
+    /// result contains every event that is neither one of those nor an ancestor of one of those.
+    /// Hence it is complete: it includes any ancestor the peer lacks, even by a creator missing
+    /// from the summary. The result is sorted topologically so that the peer can insert the events
+    /// in order without any of them missing a parent.
+    pub fn events_for_peer(&self, peer_summary: &BTreeMap<N, u64>) -> Vec<EventRef<'_, N>> {
+        let held = self.events.iter().enumerate().filter_map(|(index, event)| {
+            let count = peer_summary.get(event.creator_id())?;
+            let event = EventRef { event, index };
+            if self.sequence(&event) < *count {
+                Some(index)
+            } else {
+                None
+            }
+        });
+        let known = self.closure(held);
+        let needed: Vec<usize> = (0..self.events.len())
+            .filter(|index| !known.contains(index))
+            .collect();
+        self.sort_topologically(&needed)
+            .into_iter()
+            .filter_map(|index| self.get_by_index(index))
+            .collect()
+    }
+
+    /// Gets the Lamport timestamp of a new event with the given parents: one more than the
+    /// greatest timestamp of the parents in the graph, or 0 if there are no parents. Parents that
+    /// are not in the graph are ignored.
+    pub fn next_lamport(&self, self_parent: Option<&Hash>, other_parent: Option<&Hash>) -> u64 {
+        if self_parent.is_none() && other_parent.is_none() {
+            return 0;
+        }
+        self_parent
+            .into_iter()
+            .chain(other_parent)
+            .filter_map(|hash| self.get_by_hash(hash))
+            .map(|event| event.lamport() + 1)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Gets all events sorted by Lamport timestamp, creator and hash. The order is total and
+    /// depends only on the set of events in the graph. It is topological as long as the Lamport
+    /// timestamps of the events follow from their parents.
+    pub fn topological_order(&self) -> Vec<EventRef<'_, N>> {
+        self.order
+            .values()
+            .filter_map(|&index| self.get_by_index(index))
+            .collect()
+    }
+
+    /// Gets the actions of all events, including the pruned ones, in the topological order.
+    /// Replaying them gives the same result before and after pruning.
+    pub fn actions(&self) -> Vec<ActionRef<'_, N>> {
+        self.merge_pruned(&self.topological_order(), &self.pruned)
+    }
+
+    /// Gets the actions of the events, including the pruned ones, that are not ordered before the
+    /// given Lamport timestamp, creator and hash, in the topological order.
+    pub fn actions_from(&self, lamport: u64, creator_id: &N, hash: &Hash) -> Vec<ActionRef<'_, N>> {
+        let key = (lamport, creator_id, hash);
+        let events: Vec<EventRef<'_, N>> = self
+            .order
+            .range((lamport, creator_id.clone(), hash.clone())..)
+            .filter_map(|(_, &index)| self.get_by_index(index))
+            .collect();
+        let start = self
+            .pruned
+            .partition_point(|p| (p.lamport, &p.creator_id, &p.hash) < key);
+        self.merge_pruned(&events, &self.pruned[start..])
+    }
+
+    /// Gets the actions of the pruned events and of the events with the given hashes and all their
+    /// ancestors, in the topological order. Hashes of events that are not in the graph are
+    /// ignored.
+    pub fn causal_actions<'a, I>(&self, hashes: I) -> Vec<ActionRef<'_, N>>
+    where
+        I: IntoIterator<Item = &'a Hash>,
+    {
+        let closure = self.closure(hashes.into_iter().filter_map(|hash| self.get_index(hash)));
+        let mut events: Vec<EventRef<'_, N>> = closure
+            .into_iter()
+            .filter_map(|index| self.get_by_index(index))
+            .collect();
+        events.sort_by(|a, b| {
+            (a.lamport(), a.creator_id(), &self.hashes[a.index]).cmp(&(
+                b.lamport(),
+                b.creator_id(),
+                &self.hashes[b.index],
+            ))
+        });
+        self.merge_pruned(&events, &self.pruned)
+    }
+
+    /// Splits the events in the topological order into the older ones and at most `recent` most
+    /// recent ones. The actions of the older events, preceded by those of the pruned events that
+    /// come before them, are returned together with the older and the recent events. The recent
+    /// events always come after all pruned events.
+    #[allow(clippy::type_complexity)]
+    pub fn split_recent(
+        &self,
+        recent: usize,
+    ) -> (
+        Vec<ActionRef<'_, N>>,
+        Vec<EventRef<'_, N>>,
+        Vec<EventRef<'_, N>>,
+    ) {
+        let mut older = self.topological_order();
+        let mut start = older.len().saturating_sub(recent);
+        if let Some(last) = self.pruned.last() {
+            let last_key = (last.lamport, &last.creator_id, &last.hash);
+            let after = older
+                .iter()
+                .position(|event| {
+                    (
+                        event.lamport(),
+                        event.creator_id(),
+                        &self.hashes[event.index],
+                    ) > last_key
+                })
+                .unwrap_or(older.len());
+            start = start.max(after);
+        }
+        let recent = older.split_off(start);
+        (self.merge_pruned(&older, &self.pruned), older, recent)
+    }
+
+    /// Merges the actions of the given events, which are in the topological order, with the
+    /// given actions of pruned events.
+    fn merge_pruned<'a>(
+        &'a self,
+        events: &[EventRef<'a, N>],
+        pruned: &'a [PrunedAction<N>],
+    ) -> Vec<ActionRef<'a, N>> {
+        let pruned_ref = |p: &'a PrunedAction<N>| ActionRef {
+            creator_id: &p.creator_id,
+            hash: &p.hash,
+            lamport: p.lamport,
+            action: &p.action,
+        };
+        let mut actions = Vec::with_capacity(pruned.len() + events.len());
+        let mut pruned = pruned.iter().peekable();
+        for event in events {
+            let hash = &self.hashes[event.index];
+            let key = (event.lamport(), event.creator_id(), hash);
+            while let Some(p) = pruned.next_if(|p| (p.lamport, &p.creator_id, &p.hash) < key) {
+                actions.push(pruned_ref(p));
+            }
+            actions.push(ActionRef {
+                creator_id: event.event.creator_id(),
+                hash,
+                lamport: event.lamport(),
+                action: event.event.action(),
+            });
         }
+        actions.extend(pruned.map(pruned_ref));
+        actions
+    }
+
+    /// Removes the strict ancestors of `keep_after` that cannot be reached from any chain head
+    /// other than through `keep_after`. Returns the number of removed events.
+    ///
+    /// The actions of the removed events are retained, so the group membership replayed from the
+    /// graph doesn't change. The removed events themselves are no longer available, and neither
+    /// are events that reference them as parents considered valid by `insert_validated`.
+    pub fn prune(&mut self, keep_after: &Hash) -> Result<usize, Error<N>> {
+        let keep_index = self
+            .get_index(keep_after)
+            .ok_or_else(|| Error::UnknownEvent {
+                hash: keep_after.clone(),
+            })?;
+        let mut prunable = self.closure(iter::once(keep_index));
+        prunable.remove(&keep_index);
+        // The events reachable from a chain head, including the heads of forks, without passing
+        // through `keep_after`.
+        let mut reachable = BTreeSet::new();
+        let mut stack: Vec<usize> = self
+            .heads
+            .values()
+            .flatten()
+            .cloned()
+            .filter(|&index| index != keep_index)
+            .collect();
+        while let Some(index) = stack.pop() {
+            if reachable.insert(index) {
+                stack.extend(
+                    self.parent_indices(index)
+                        .filter(|&parent| parent != keep_index),
+                );
+            }
+        }
+        prunable.retain(|index| !reachable.contains(index));
+        if prunable.is_empty() {
+            return Ok(0);
+        }
+        let mut pruned: Vec<PrunedAction<N>> = prunable
+            .iter()
+            .map(|&index| {
+                let event = &self.events[index];
+                PrunedAction {
+                    lamport: event.lamport(),
+                    creator_id: event.creator_id().clone(),
+                    hash: self.hashes[index].clone(),
+                    action: event.action().clone(),
+                }
+            })
+            .collect();
+        let mut offsets = std::mem::take(&mut self.offsets);
+        for action in &pruned {
+            *offsets.entry(action.creator_id.clone()).or_insert(0) += 1;
+        }
+        pruned.append(&mut self.pruned);
+        pruned.sort_by(|a, b| {
+            (a.lamport, &a.creator_id, &a.hash).cmp(&(b.lamport, &b.creator_id, &b.hash))
+        });
+        let events = std::mem::take(&mut self.events);
+        *self = Graph::with_base_summary(offsets);
+        self.pruned = pruned;
+        for (index, event) in events.into_iter().enumerate() {
+            if !prunable.contains(&index) {
+                self.insert(event)?;
+            }
+        }
+        Ok(prunable.len())
+    }
+
+    /// Gets the events that a peer with the given tips is missing, parents before children. The
+    /// peer is assumed to hold every ancestor of its tips. Tips unknown to this graph are ignored.
+    ///
+    /// The search walks back from the local chain heads and stops at events that the peer holds,
+    /// which are the events no later on their creator's chain than the peer's tip of that creator.
+    pub fn events_missing_from(&self, tips: &BTreeMap<N, Hash>) -> Vec<EventRef<'_, N>> {
+        let tip_lamports: BTreeMap<&N, u64> = tips
+            .iter()
+            .filter_map(|(creator, hash)| Some((creator, self.get_by_hash(hash)?.lamport())))
+            .collect();
+        let is_known = |index: usize| {
+            let event = &self.events[index];
+            tip_lamports
+                .get(event.creator_id())
+                .is_some_and(|&lamport| event.lamport() <= lamport)
+        };
+        let mut needed = BTreeSet::new();
+        let mut stack: Vec<usize> = self.heads.values().flatten().cloned().collect();
+        while let Some(index) = stack.pop() {
+            if !is_known(index) && needed.insert(index) {
+                stack.extend(self.parent_indices(index));
+            }
+        }
+        let needed: Vec<usize> = needed.into_iter().collect();
+        self.sort_topologically(&needed)
+            .into_iter()
+            .filter_map(|index| self.get_by_index(index))
+            .collect()
+    }
+
+    /// Gets all events in the canonical order: parents precede their children and otherwise events
+    /// are ordered by hash. The order depends only on the set of events in the graph and not on the
+    /// order in which they were inserted.
+    pub fn canonical_order(&self) -> Vec<EventRef<'_, N>> {
+        self.canonical_indices()
+            .into_iter()
+            .filter_map(|index| self.get_by_index(index))
+            .collect()
+    }
+
+    /// Gets the indices of all events in the canonical order.
+    fn canonical_indices(&self) -> Vec<usize> {
+        let by_hash: Vec<usize> = self.indices.values().cloned().collect();
+        self.sort_topologically(&by_hash)
+    }
+
+    /// Gets the summary of the graph, which is the number of events by each creator, including
+    /// the pruned and base events.
+    pub fn summary(&self) -> BTreeMap<N, u64> {
+        let mut summary = self.offsets.clone();
+        for event in &self.events {
+            *summary.entry(event.creator_id().clone()).or_insert(0) += 1;
+        }
+        summary
+    }
+
+    /// Checks whether `ancestor` is an ancestor of `descendant` or equal to it. The search follows
+    /// parent links from `descendant` and stops as soon as `ancestor` is reached. Returns `false`
+    /// if either event is not in the graph.
+    pub fn is_ancestor(&self, ancestor: &Hash, descendant: &Hash) -> bool {
+        let (target, start) = match (self.get_index(ancestor), self.get_index(descendant)) {
+            (Some(target), Some(start)) => (target, start),
+            _ => return false,
+        };
+        let mut visited = BTreeSet::new();
+        let mut stack = vec![start];
+        while let Some(index) = stack.pop() {
+            if index == target {
+                return true;
+            }
+            if visited.insert(index) {
+                stack.extend(self.parent_indices(index));
+            }
+        }
+        false
+    }
+
+    /// Checks whether `a` strongly sees `b`: `b` is an ancestor of `a` or equal to it, and the
+    /// events on the paths from `b` to `a` have at least `supermajority` distinct creators.
+    /// Returns `false` if either event is not in the graph.
+    pub fn strongly_sees(&self, a: &Hash, b: &Hash, supermajority: usize) -> bool {
+        match (self.get_index(a), self.get_index(b)) {
+            (Some(a), Some(b)) => self.strongly_sees_index(a, b, supermajority),
+            _ => false,
+        }
+    }
+
+    /// Checks whether the event with index `a` strongly sees the event with index `b`.
+    fn strongly_sees_index(&self, a: usize, b: usize, supermajority: usize) -> bool {
+        let ancestors = self.closure(iter::once(a));
+        if !ancestors.contains(&b) {
+            return false;
+        }
+        let mut creators = BTreeSet::new();
+        creators.insert(self.events[b].creator_id());
+        for event in self.descendants(EventRef {
+            event: &self.events[b],
+            index: b,
+        }) {
+            if ancestors.contains(&event.index) {
+                creators.insert(event.event.creator_id());
+            }
+        }
+        creators.len() >= supermajority
+    }
+
+    /// Gets the round of an event. Events without parents are in round 0. Every other event is
+    /// in the greatest round `r` of its parents, or in round `r + 1` if it strongly sees the
+    /// round `r` witnesses of at least `supermajority` creators. A witness is the first event of
+    /// its creator in a round.
+    ///
+    /// The supermajority is given by the caller, typically as more than two thirds of the group
+    /// at the event, so that the round doesn't change as events of new creators arrive.
+    pub fn round(&self, event: &Hash, supermajority: usize) -> Option<u64> {
+        let index = self.get_index(event)?;
+        let ancestors = self.closure(iter::once(index));
+        let order: Vec<usize> = self
+            .canonical_indices()
+            .into_iter()
+            .filter(|index| ancestors.contains(index))
+            .collect();
+        // The rounds and the ancestor closures of the events processed so far, so that neither is
+        // computed more than once per event.
+        let mut rounds: BTreeMap<usize, u64> = BTreeMap::new();
+        let mut closures: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        let mut witnesses: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        for index in order {
+            let mut closure: BTreeSet<usize> = iter::once(index).collect();
+            for parent in self.parent_indices(index) {
+                closure.extend(closures[&parent].iter().cloned());
+            }
+            let strongly_sees = |witness: usize| {
+                if !closure.contains(&witness) {
+                    return false;
+                }
+                let creators: BTreeSet<&N> = closure
+                    .iter()
+                    .filter(|&&on_path| on_path == index || closures[&on_path].contains(&witness))
+                    .map(|&on_path| self.events[on_path].creator_id())
+                    .collect();
+                creators.len() >= supermajority
+            };
+            let round = match self
+                .parent_indices(index)
+                .map(|parent| rounds[&parent])
+                .max()
+            {
+                None => 0,
+                Some(round) => {
+                    let seen: BTreeSet<&N> = witnesses
+                        .get(&round)
+                        .into_iter()
+                        .flatten()
+                        .filter(|&&witness| strongly_sees(witness))
+                        .map(|&witness| self.events[witness].creator_id())
+                        .collect();
+                    if seen.len() >= supermajority {
+                        round + 1
+                    } else {
+                        round
+                    }
+                }
+            };
+            let self_parent_round = self.events[index]
+                .self_parent()
+                .and_then(|hash| self.get_index(hash))
+                .map(|parent| rounds[&parent]);
+            if self_parent_round.is_none_or(|parent_round| parent_round < round) {
+                witnesses.entry(round).or_default().push(index);
+            }
+            rounds.insert(index, round);
+            closures.insert(index, closure);
+        }
+        rounds.get(&index).cloned()
+    }
+
+    /// Gets the tips of the graph: the hash of the head of the self-parent chain of each creator.
+    /// A head is an event that is not the self-parent of another event by the same creator. If a
+    /// creator has forked and so has several heads, the highest-indexed head is returned.
+    pub fn tips(&self) -> BTreeMap<N, Hash> {
+        self.heads
+            .iter()
+            .filter_map(|(creator, heads)| {
+                let index = heads.iter().next_back()?;
+                Some((creator.clone(), self.hashes[*index].clone()))
+            })
+            .collect()
+    }
+
+    /// Gets the events of a creator along its self-parent chain, the earliest first. The chain is
+    /// followed back from the tip of the creator, so if the creator has forked, only the branch
+    /// of the tip is yielded.
+    pub fn events_by_creator<'a>(&'a self, creator: &N) -> impl Iterator<Item = EventRef<'a, N>> {
+        let mut chain = Vec::new();
+        let mut next = self
+            .tips()
+            .get(creator)
+            .and_then(|hash| self.get_by_hash(hash));
+        while let Some(event) = next {
+            next = event
+                .self_parent()
+                .and_then(|hash| self.get_by_hash(hash))
+                .filter(|parent| parent.creator_id() == creator);
+            chain.push(event);
+        }
+        chain.into_iter().rev()
+    }
+
+    /// Renders the graph in the Graphviz DOT format. Events are labelled with the first 8 hex
+    /// digits of their hash and clustered by creator. Solid edges point to self-parents and
+    /// dashed edges to other-parents. Parents that are not in the graph are omitted.
+    pub fn to_dot(&self) -> String {
+        let escape = |s: String| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut by_creator: BTreeMap<&N, Vec<usize>> = BTreeMap::new();
+        for (index, event) in self.events.iter().enumerate() {
+            by_creator
+                .entry(event.creator_id())
+                .or_default()
+                .push(index);
+        }
+        let mut dot = String::from("digraph gossip {\n    rankdir=BT;\n");
+        for (cluster, (creator, indices)) in by_creator.into_iter().enumerate() {
+            let creator = escape(format!("{:?}", creator));
+            dot += &format!("    subgraph cluster_{} {{\n", cluster);
+            dot += &format!("        label=\"{}\";\n", creator);
+            for index in indices {
+                let hash = self.hashes[index].to_string();
+                dot += &format!(
+                    "        e{} [label=\"{}\\n{}\"];\n",
+                    index,
+                    &hash[..8],
+                    creator
+                );
+            }
+            dot += "    }\n";
+        }
+        for (index, event) in self.events.iter().enumerate() {
+            if let Some(parent) = event.self_parent().and_then(|hash| self.get_index(hash)) {
+                dot += &format!("    e{} -> e{};\n", index, parent);
+            }
+            if let Some(parent) = event.other_parent().and_then(|hash| self.get_index(hash)) {
+                dot += &format!("    e{} -> e{} [style=dashed];\n", index, parent);
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    /// Gets the lag of `b` relative to `a`: the number of events created by `a` that are not
+    /// ancestors of the tip of `b`, as returned by `tips`.
+    pub fn causal_lag(&self, a: &N, b: &N) -> usize {
+        let tip = self
+            .heads
+            .get(b)
+            .and_then(|heads| heads.iter().next_back().cloned());
+        let seen = self.closure(tip);
+        self.events
+            .iter()
+            .enumerate()
+            .filter(|(index, event)| event.creator_id() == a && !seen.contains(index))
+            .count()
+    }
+
+    /// Gets the earliest `Init` or `Add` event that references the given node. Events are ordered
+    /// by causal depth with ties broken by hash.
+    ///
+    /// The returned event is the first introduction of the node anywhere in the graph regardless
+    /// of whether the node was removed and added again later, so it need not be the event that
+    /// introduced the node into the current group.
+    pub fn first_add_of(&self, node: &N) -> Option<EventRef<'_, N>> {
+        let depths = self.depths();
+        self.events
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| match event.action() {
+                Action::Init(members) => members.contains(node),
+                Action::Add(added) => added == node,
+                Action::Remove(_) => false,
+            })
+            .min_by_key(|(index, _)| (depths[*index], &self.hashes[*index]))
+            .map(|(index, event)| EventRef { event, index })
+    }
+
+    /// Gets the causal depth of each event, that is, the length of the longest chain of its
+    /// ancestors in the graph.
+    fn depths(&self) -> Vec<u64> {
+        let mut depths = vec![0; self.events.len()];
+        for index in self.canonical_indices() {
+            depths[index] = self
+                .parent_indices(index)
+                .map(|parent| depths[parent] + 1)
+                .max()
+                .unwrap_or(0);
+        }
+        depths
+    }
+
+    /// Gets the indices of the parents of the event with the given index that are in the graph.
+    fn parent_indices(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let event = &self.events[index];
+        event
+            .self_parent()
+            .into_iter()
+            .chain(event.other_parent())
+            .filter_map(move |hash| self.get_index(hash))
+    }
+
+    /// Gets the indices of the given events together with the indices of all their ancestors.
+    fn closure<I: IntoIterator<Item = usize>>(&self, indices: I) -> BTreeSet<usize> {
+        let mut closure = BTreeSet::new();
+        let mut stack: Vec<usize> = indices.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            if closure.insert(index) {
+                stack.extend(self.parent_indices(index));
+            }
+        }
+        closure
+    }
+
+    /// Sorts the given event indices such that parents precede their children. Apart from that,
+    /// the given order is preserved as far as possible.
+    fn sort_topologically(&self, order: &[usize]) -> Vec<usize> {
+        let indices: BTreeSet<usize> = order.iter().cloned().collect();
+        let mut visited = BTreeSet::new();
+        let mut sorted = Vec::with_capacity(indices.len());
+        for &start in order {
+            // The flag marks an entry whose parents have already been pushed.
+            let mut stack = vec![(start, false)];
+            while let Some((index, expanded)) = stack.pop() {
+                if expanded {
+                    sorted.push(index);
+                } else if visited.insert(index) {
+                    stack.push((index, true));
+                    stack.extend(
+                        self.parent_indices(index)
+                            .filter(|parent| indices.contains(parent) && !visited.contains(parent))
+                            .map(|parent| (parent, false)),
+                    );
+                }
+            }
+        }
+        sorted
     }
 }
 
 /// The state of an iterator over the ancestors of an `Event` in a `Graph`.
-pub struct AncestorIter<'a, N: NodeId + 'a> {
+pub struct AncestorIter<'a, N: NodeId + 'a, H: Hasher = Sha3Hasher> {
     /// The original graph.
-    pub graph: &'a Graph<N>,
-    /// The queue of nodes to be traversed through to their ancestors.
-    pub queue: VecDeque<EventRef<'a, N>>,
+    pub graph: &'a Graph<N, H>,
+    /// The queue of nodes to be traversed through to their ancestors, with the number of
+    /// generations between each node and the start event.
+    pub queue: VecDeque<(EventRef<'a, N>, usize)>,
+    /// The number of generations beyond which ancestors are not traversed.
+    pub max_depth: Option<usize>,
+    /// The indices of events that have been queued already. Since the queue is traversed in
+    /// the FIFO order, every event is queued at its minimal depth.
+    pub visited: BTreeSet<usize>,
 }
 
-impl<'a, N: NodeId + 'a> Iterator for AncestorIter<'a, N> {
+impl<'a, N: NodeId + 'a, H: Hasher> Iterator for AncestorIter<'a, N, H> {
     type Item = EventRef<'a, N>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let event = self.queue.pop_back()?;
-        let mut add_parent = |parent: Option<&Hash>| {
-            parent
-                .and_then(|hash| self.graph.get_by_hash(hash))
-                .map(|parent| self.queue.push_front(parent))
-        };
-        add_parent(event.other_parent());
-        add_parent(event.self_parent());
+        let (event, depth) = self.queue.pop_back()?;
+        if self.max_depth.is_none_or(|max_depth| depth < max_depth) {
+            let mut add_parent = |parent: Option<&Hash>| {
+                if let Some(parent) = parent.and_then(|hash| self.graph.get_by_hash(hash)) {
+                    if self.visited.insert(parent.index) {
+                        self.queue.push_front((parent, depth + 1));
+                    }
+                }
+            };
+            add_parent(event.other_parent());
+            add_parent(event.self_parent());
+        }
         Some(event)
     }
 }
+
+/// The state of an iterator over the descendants of an `Event` in a `Graph`.
+pub struct DescendantIter<'a, N: NodeId + 'a, H: Hasher = Sha3Hasher> {
+    /// The original graph.
+    graph: &'a Graph<N, H>,
+    /// The queue of indices of events to be yielded and traversed through to their children.
+    queue: VecDeque<usize>,
+    /// The indices of events that have been queued already.
+    visited: BTreeSet<usize>,
+}
+
+impl<'a, N: NodeId + 'a, H: Hasher> DescendantIter<'a, N, H> {
+    /// Queues the children of an event that haven't been queued yet.
+    fn push_children(&mut self, index: usize) {
+        for &child in self.graph.child_indices(index) {
+            if self.visited.insert(child) {
+                self.queue.push_back(child);
+            }
+        }
+    }
+}
+
+impl<'a, N: NodeId + 'a, H: Hasher> Iterator for DescendantIter<'a, N, H> {
+    type Item = EventRef<'a, N>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.queue.pop_front()?;
+        self.push_children(index);
+        self.graph.get_by_index(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::{Action, Error, Event, Graph};
+    use crate::hash::{compute_hash, Hash, Hasher};
+
+    /// Inserts an event into the graph and returns its hash.
+    fn insert(
+        graph: &mut Graph<u64>,
+        creator_id: u64,
+        self_parent: Option<&Hash>,
+        other_parent: Option<&Hash>,
+        action: Action<u64>,
+    ) -> Hash {
+        let lamport = graph.next_lamport(self_parent, other_parent);
+        let event = Event::new(
+            creator_id,
+            self_parent.cloned(),
+            other_parent.cloned(),
+            lamport,
+            action,
+        );
+        let hash = event.hash().unwrap();
+        graph.insert(event).unwrap();
+        hash
+    }
+
+    #[test]
+    fn events_for_peer_includes_missing_ancestors() {
+        let mut graph = Graph::new();
+        let init = Action::Init(vec![0, 1].into_iter().collect::<BTreeSet<_>>());
+        let a0 = insert(&mut graph, 0, None, None, init);
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        let a1 = insert(&mut graph, 0, Some(&a0), Some(&b0), Action::Add(3));
+        // The peer only holds `a0`. A naive set of events by creator 0 newer than `a0` would
+        // omit `b0`, which is the other-parent of `a1`.
+        let summary: BTreeMap<u64, u64> = vec![(0, 1)].into_iter().collect();
+        let hashes: Vec<Hash> = graph
+            .events_for_peer(&summary)
+            .into_iter()
+            .map(|event| event.event.hash().unwrap())
+            .collect();
+        assert_eq!(hashes, vec![b0, a1]);
+        // A peer holding everything needs nothing.
+        let summary = vec![(0, 2), (1, 1)].into_iter().collect();
+        assert!(graph.events_for_peer(&summary).is_empty());
+    }
+
+    #[test]
+    fn events_missing_from_stops_at_peer_tips() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        let a1 = insert(&mut graph, 0, Some(&a0), Some(&b0), Action::Add(3));
+        let missing = |tips: BTreeMap<u64, Hash>| -> Vec<Hash> {
+            graph
+                .events_missing_from(&tips)
+                .into_iter()
+                .map(|event| event.event.hash().unwrap())
+                .collect()
+        };
+        assert_eq!(
+            missing(BTreeMap::new()),
+            vec![a0.clone(), b0.clone(), a1.clone()]
+        );
+        assert_eq!(
+            missing(vec![(0, a0.clone())].into_iter().collect()),
+            vec![b0.clone(), a1.clone()]
+        );
+        assert!(missing(vec![(0, a1), (1, b0)].into_iter().collect()).is_empty());
+    }
+
+    #[test]
+    fn creators_are_counted_once() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(3));
+        let a1 = insert(&mut graph, 0, Some(&a0), Some(&b0), Action::Add(4));
+        insert(&mut graph, 2, None, Some(&a1), Action::Remove(1));
+        assert_eq!(graph.creator_count(), 3);
+        assert_eq!(graph.creators(), vec![0, 1, 2].into_iter().collect());
+    }
+
+    #[test]
+    fn parents_are_resolved() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        let a1 = insert(&mut graph, 0, Some(&a0), Some(&b0), Action::Add(3));
+        let missing = compute_hash(&"missing").unwrap();
+        let b1 = insert(&mut graph, 1, Some(&b0), Some(&missing), Action::Add(4));
+        let (self_parent, other_parent) = graph.parents(graph.get_by_hash(&a1).unwrap());
+        assert_eq!(self_parent, graph.get_by_hash(&a0));
+        assert_eq!(other_parent, graph.get_by_hash(&b0));
+        let (self_parent, other_parent) = graph.parents(graph.get_by_hash(&a0).unwrap());
+        assert!(self_parent.is_none());
+        assert!(other_parent.is_none());
+        let (self_parent, other_parent) = graph.parents(graph.get_by_hash(&b1).unwrap());
+        assert_eq!(self_parent, graph.get_by_hash(&b0));
+        assert!(other_parent.is_none());
+    }
+
+    #[test]
+    fn causal_lag_counts_unseen_events() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(2));
+        insert(&mut graph, 1, None, Some(&a1), Action::Add(3));
+        let a2 = insert(&mut graph, 0, Some(&a1), None, Action::Add(4));
+        insert(&mut graph, 0, Some(&a2), None, Action::Add(5));
+        // 1 has seen `a0` and `a1` but not the two later events of 0.
+        assert_eq!(graph.causal_lag(&0, &1), 2);
+        // 0 hasn't seen the only event of 1.
+        assert_eq!(graph.causal_lag(&1, &0), 1);
+        assert_eq!(graph.causal_lag(&0, &0), 0);
+    }
+
+    #[test]
+    fn causal_lag_follows_tip_after_out_of_order_insertion() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(2));
+        let b0 = Event::new(1, None, Some(a0), 1, Action::Add(3));
+        let b1 = Event::new(1, Some(b0.hash().unwrap()), Some(a1), 2, Action::Add(4));
+        // The tip of 1 is inserted before its self-parent.
+        graph.insert(b1).unwrap();
+        graph.insert(b0).unwrap();
+        // The tip of 1 has seen both events of 0, even though `b0` was inserted last.
+        assert_eq!(graph.causal_lag(&0, &1), 0);
+    }
+
+    #[test]
+    fn descendants_of_diamond_root() {
+        let mut graph = Graph::new();
+        let root = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let left = insert(&mut graph, 0, Some(&root), None, Action::Add(1));
+        let right = insert(&mut graph, 1, None, Some(&root), Action::Add(2));
+        let bottom = insert(&mut graph, 0, Some(&left), Some(&right), Action::Add(3));
+        let descendants = |hash| -> BTreeSet<Hash> {
+            graph
+                .descendants(graph.get_by_hash(hash).unwrap())
+                .map(|event| graph.get_hash(event.index).unwrap().clone())
+                .collect()
+        };
+        let root_descendants: Vec<Hash> = graph
+            .descendants(graph.get_by_hash(&root).unwrap())
+            .map(|event| graph.get_hash(event.index).unwrap().clone())
+            .collect();
+        assert_eq!(root_descendants.len(), 3);
+        assert_eq!(
+            descendants(&root),
+            vec![left.clone(), right, bottom.clone()]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            descendants(&left),
+            vec![bottom.clone()].into_iter().collect()
+        );
+        assert!(descendants(&bottom).is_empty());
+    }
+
+    #[test]
+    fn first_add_of_is_earliest() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(5));
+        let a2 = insert(&mut graph, 0, Some(&a1), None, Action::Remove(5));
+        insert(&mut graph, 0, Some(&a2), None, Action::Add(5));
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(5));
+        let first = graph.first_add_of(&5).unwrap();
+        let expected = a1.min(b0);
+        assert_eq!(graph.get_hash(first.index), Some(&expected));
+        assert!(graph.first_add_of(&6).is_none());
+    }
+
+    #[test]
+    fn insert_checked_detects_forks() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(1));
+        // Sharing an other-parent is legitimate.
+        let b0 = Event::new(1, None, Some(a1.clone()), 2, Action::Add(2));
+        let b0_hash = b0.hash().unwrap();
+        graph.insert_checked(b0).unwrap();
+        let c0 = Event::new(2, None, Some(a1.clone()), 2, Action::Add(3));
+        graph.insert_checked(c0).unwrap();
+        // Reinserting a known event is not a fork.
+        let a1_event = Event::new(0, Some(a0.clone()), None, 1, Action::Add(1));
+        graph.insert_checked(a1_event).unwrap();
+        let fork = Event::new(0, Some(a0.clone()), Some(b0_hash), 3, Action::Add(4));
+        let fork_hash = fork.hash().unwrap();
+        match graph.insert_checked(fork) {
+            Err(Error::Fork { creator, hashes }) => {
+                assert_eq!(creator, 0);
+                assert_eq!(hashes, vec![a1, fork_hash]);
+            }
+            r => panic!("unexpected result {:?}", r.map(|event| event.index)),
+        }
+    }
+
+    #[test]
+    fn insert_detects_hash_collisions() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = Event::new(1, None, None, 0, Action::Init(BTreeSet::new()));
+        let b0_hash = b0.hash().unwrap();
+        // Stub the hash of `b0` to point to the entry of `a0`.
+        let a0_index = graph.get_index(&a0).unwrap();
+        graph.indices.insert(b0_hash.clone(), a0_index);
+        match graph.insert(b0) {
+            Err(Error::HashCollision { hash }) => assert_eq!(hash, b0_hash),
+            r => panic!("unexpected result {:?}", r.map(|event| event.index)),
+        }
+    }
+
+    #[test]
+    fn signature_is_not_part_of_identity() {
+        let mut graph: Graph<u64> = Graph::new();
+        let unsigned = Event::new(0, None, None, 0, Action::Init(BTreeSet::new()));
+        let signed = unsigned.clone().with_signature(vec![1]);
+        let resigned = unsigned.clone().with_signature(vec![2]);
+        let hash = signed.hash().unwrap();
+        assert_eq!(resigned.hash().unwrap(), hash);
+        assert_eq!(unsigned.hash().unwrap(), hash);
+        graph.insert(signed).unwrap();
+        // Copies with another signature or none are the same event.
+        assert_eq!(graph.insert(resigned).unwrap().index, 0);
+        assert_eq!(graph.insert(unsigned).unwrap().index, 0);
+        assert_eq!(
+            graph.get_by_hash(&hash).unwrap().event.signature(),
+            Some(&[1][..])
+        );
+    }
+
+    /// A hasher that maps everything to the same hash.
+    #[derive(Clone, Debug)]
+    struct ConstHasher;
+
+    impl Hasher for ConstHasher {
+        fn hash(_bytes: &[u8]) -> Hash {
+            Hash([0; 32])
+        }
+    }
+
+    #[test]
+    fn insert_uses_graph_hasher() {
+        let mut graph: Graph<u64, ConstHasher> = Graph::new();
+        let a0 = Event::new(0, None, None, 0, Action::Init(BTreeSet::new()));
+        graph.insert(a0).unwrap();
+        assert!(graph.contains(&Hash([0; 32])));
+        let b0 = Event::new(1, None, None, 0, Action::Init(BTreeSet::new()));
+        match graph.insert(b0) {
+            Err(Error::HashCollision { hash }) => assert_eq!(hash, Hash([0; 32])),
+            r => panic!("unexpected result {:?}", r.map(|event| event.index)),
+        }
+    }
+
+    #[test]
+    fn serialization_is_independent_of_insertion_order() {
+        let init = Event::new(0, None, None, 0, Action::Init(BTreeSet::new()));
+        let init_hash = init.hash().unwrap();
+        let children: Vec<Event<u64>> = (1..4)
+            .map(|i| Event::new(i, None, Some(init_hash.clone()), 1, Action::Add(i)))
+            .collect();
+        let mut graph1: Graph<u64> = Graph::new();
+        graph1.insert(init.clone()).unwrap();
+        for event in children.iter() {
+            graph1.insert(event.clone()).unwrap();
+        }
+        let mut graph2: Graph<u64> = Graph::new();
+        for event in children.iter().rev() {
+            graph2.insert(event.clone()).unwrap();
+        }
+        graph2.insert(init).unwrap();
+        let bytes1 = bincode::serialize(&graph1).unwrap();
+        let bytes2 = bincode::serialize(&graph2).unwrap();
+        assert_eq!(bytes1, bytes2);
+        // The indices are reconstructed on deserialization.
+        let graph3: Graph<u64> = bincode::deserialize(&bytes1).unwrap();
+        assert!(graph3.contains(&init_hash));
+        assert_eq!(bincode::serialize(&graph3).unwrap(), bytes1);
+    }
+
+    #[test]
+    fn snapshot_round_trip_resolves_every_event() {
+        let mut graph = Graph::new();
+        let init = Action::Init(vec![0, 1].into_iter().collect::<BTreeSet<_>>());
+        let a0 = insert(&mut graph, 0, None, None, init);
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        let a1 = insert(&mut graph, 0, Some(&a0), Some(&b0), Action::Remove(1));
+        let c0 = insert(&mut graph, 2, None, Some(&a1), Action::Add(3));
+        let restored: Graph<u64> = Graph::from_bytes(&graph.to_bytes().unwrap()).unwrap();
+        for hash in [a0, b0, a1, c0].iter() {
+            let event = restored.get_by_hash(hash).unwrap();
+            assert_eq!(
+                event.event,
+                graph.get_by_hash(hash).unwrap().event,
+                "event {} differs",
+                hash
+            );
+        }
+        assert_eq!(restored.creators(), graph.creators());
+    }
+
+    #[test]
+    fn snapshot_of_another_version_is_rejected() {
+        let mut graph = Graph::new();
+        insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        // A snapshot from before versioning is the bare serialized graph.
+        let unversioned = bincode::serialize(&graph).unwrap();
+        match Graph::<u64>::from_bytes(&unversioned) {
+            Err(Error::SnapshotVersion(None)) => {}
+            r => panic!("unexpected result {:?}", r.map(|graph| graph.creators())),
+        }
+        let mut bytes = graph.to_bytes().unwrap();
+        bytes[4] = 1;
+        match Graph::<u64>::from_bytes(&bytes) {
+            Err(Error::SnapshotVersion(Some(1))) => {}
+            r => panic!("unexpected result {:?}", r.map(|graph| graph.creators())),
+        }
+    }
+
+    #[test]
+    fn tips_are_chain_heads() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(1));
+        let b0 = insert(&mut graph, 1, None, Some(&a1), Action::Add(2));
+        let a2 = insert(&mut graph, 0, Some(&a1), Some(&b0), Action::Add(3));
+        let tips = graph.tips();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips.get(&0), Some(&a2));
+        assert_eq!(tips.get(&1), Some(&b0));
+        // The heads don't depend on the order of insertion.
+        let mut reversed: Graph<u64> = Graph::new();
+        for event in graph.canonical_order().into_iter().rev() {
+            reversed.insert(event.event.clone()).unwrap();
+        }
+        assert_eq!(reversed.tips(), tips);
+    }
+
+    #[test]
+    fn is_ancestor_follows_parents() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        let c0 = insert(&mut graph, 2, None, Some(&a0), Action::Add(3));
+        let b1 = insert(&mut graph, 1, Some(&b0), Some(&c0), Action::Add(4));
+        assert!(graph.is_ancestor(&a0, &b1));
+        assert!(graph.is_ancestor(&c0, &b1));
+        assert!(!graph.is_ancestor(&b0, &c0));
+        assert!(!graph.is_ancestor(&b1, &a0));
+        assert!(graph.is_ancestor(&b0, &b0));
+        let unknown = Hash([0; 32]);
+        assert!(!graph.is_ancestor(&unknown, &b1));
+        assert!(!graph.is_ancestor(&a0, &unknown));
+        assert!(!graph.is_ancestor(&unknown, &unknown));
+    }
+
+    #[test]
+    fn merge_inserts_missing_events_in_order() {
+        let mut graph1 = Graph::new();
+        let a0 = insert(&mut graph1, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph1, 1, None, Some(&a0), Action::Add(2));
+        let mut graph2 = Graph::new();
+        insert(&mut graph2, 0, None, None, Action::Init(BTreeSet::new()));
+        let c0 = insert(&mut graph2, 2, None, Some(&a0), Action::Add(3));
+        let c1 = insert(&mut graph2, 2, Some(&c0), None, Action::Add(4));
+        assert_eq!(graph1.merge(&graph2).unwrap(), vec![c0.clone(), c1.clone()]);
+        for hash in [&a0, &b0, &c0, &c1].iter() {
+            assert!(graph1.contains(hash));
+        }
+        assert!(graph1.merge(&graph2).unwrap().is_empty());
+        let added = graph2.merge(&graph1).unwrap();
+        assert_eq!(added, vec![b0]);
+        assert_eq!(graph1.to_bytes().unwrap(), graph2.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn merge_rejects_orphans() {
+        let mut graph1 = Graph::new();
+        insert(&mut graph1, 0, None, None, Action::Init(BTreeSet::new()));
+        let mut graph2 = Graph::new();
+        let missing = Hash([0; 32]);
+        let orphan = insert(&mut graph2, 1, Some(&missing), None, Action::Add(2));
+        match graph1.merge(&graph2) {
+            Err(Error::Orphan { hash, parent }) => {
+                assert_eq!(hash, orphan);
+                assert_eq!(parent, missing);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(graph1.summary().len(), 1);
+    }
+
+    #[test]
+    fn insert_validated_rejects_missing_parents() {
+        let mut graph: Graph<u64> = Graph::new();
+        let genesis = Event::new(0, None, None, 0, Action::Init(BTreeSet::new()));
+        let genesis_hash = genesis.hash().unwrap();
+        graph.insert_validated(genesis).unwrap();
+        let a1 = Event::new(0, Some(genesis_hash.clone()), None, 1, Action::Add(1));
+        let a1_hash = a1.hash().unwrap();
+        graph.insert_validated(a1).unwrap();
+        let missing = Hash([0; 32]);
+        let b0 = Event::new(1, Some(a1_hash), Some(missing.clone()), 2, Action::Add(2));
+        match graph.insert_validated(b0) {
+            Err(Error::MissingParent { hash }) => assert_eq!(hash, missing),
+            r => panic!("unexpected result {:?}", r.map(|event| event.index)),
+        }
+        assert_eq!(graph.summary().values().sum::<u64>(), 2);
+        assert!(graph.contains(&genesis_hash));
+    }
+
+    #[test]
+    fn to_dot_declares_events_and_edges() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        insert(&mut graph, 0, Some(&a0), Some(&b0), Action::Add(3));
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph gossip {"));
+        assert_eq!(dot.matches("subgraph cluster_").count(), 2);
+        assert_eq!(dot.matches(" [label=").count(), 3);
+        assert_eq!(dot.matches(" -> ").count(), 3);
+        assert_eq!(dot.matches("[style=dashed]").count(), 2);
+        assert!(dot.contains(&a0.to_string()[..8]));
+    }
+
+    #[test]
+    fn events_by_creator_follow_self_parent_chain() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        let a1 = insert(&mut graph, 0, Some(&a0), Some(&b0), Action::Add(3));
+        let b1 = insert(&mut graph, 1, Some(&b0), Some(&a1), Action::Add(4));
+        let a2 = insert(&mut graph, 0, Some(&a1), Some(&b1), Action::Add(5));
+        let chain = |creator: u64| -> Vec<Hash> {
+            graph
+                .events_by_creator(&creator)
+                .map(|event| event.event.hash().unwrap())
+                .collect()
+        };
+        assert_eq!(chain(0), vec![a0, a1, a2]);
+        assert_eq!(chain(1), vec![b0, b1]);
+        assert!(chain(2).is_empty());
+    }
+
+    #[test]
+    fn topological_order_is_independent_of_insertion_order() {
+        let mut graph1 = Graph::new();
+        let a0 = insert(&mut graph1, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph1, 1, None, Some(&a0), Action::Add(2));
+        let c0 = insert(&mut graph1, 2, None, Some(&a0), Action::Add(3));
+        let a1 = insert(&mut graph1, 0, Some(&a0), Some(&c0), Action::Add(4));
+        let events: Vec<Event<u64>> = graph1
+            .topological_order()
+            .into_iter()
+            .map(|event| event.event.clone())
+            .collect();
+        let mut graph2: Graph<u64> = Graph::new();
+        for event in events.iter().rev() {
+            graph2.insert(event.clone()).unwrap();
+        }
+        let hashes = |graph: &Graph<u64>| -> Vec<Hash> {
+            graph
+                .topological_order()
+                .into_iter()
+                .map(|event| event.event.hash().unwrap())
+                .collect()
+        };
+        assert_eq!(hashes(&graph1), vec![a0, b0, c0, a1]);
+        assert_eq!(hashes(&graph2), hashes(&graph1));
+        let lamports: Vec<u64> = events.iter().map(Event::lamport).collect();
+        assert_eq!(lamports, vec![0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn ancestors_within_are_bounded() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        let a1 = insert(&mut graph, 0, Some(&a0), Some(&b0), Action::Add(3));
+        let b1 = insert(&mut graph, 1, Some(&b0), Some(&a1), Action::Add(4));
+        let a2 = insert(&mut graph, 0, Some(&a1), Some(&b1), Action::Add(5));
+        // Sorted, so that any ancestor yielded twice shows.
+        let within = |max_depth: usize| -> Vec<Hash> {
+            let start = graph.get_by_hash(&a2).unwrap();
+            let mut hashes: Vec<Hash> = graph
+                .ancestors_within(start, max_depth)
+                .map(|event| event.event.hash().unwrap())
+                .collect();
+            hashes.sort();
+            hashes
+        };
+        let sorted = |hashes: &[&Hash]| -> Vec<Hash> {
+            let mut hashes: Vec<Hash> = hashes.iter().cloned().cloned().collect();
+            hashes.sort();
+            hashes
+        };
+        assert_eq!(within(0), sorted(&[&a2]));
+        assert_eq!(within(1), sorted(&[&a2, &a1, &b1]));
+        assert_eq!(within(2), sorted(&[&a2, &a1, &b1, &a0, &b0]));
+        let start = graph.get_by_hash(&a2).unwrap();
+        assert_eq!(graph.ancestors(start).count(), 5);
+        assert_eq!(within(10).len(), 5);
+    }
+
+    #[test]
+    fn prune_retains_actions() {
+        let mut graph = Graph::new();
+        let init = Action::Init(vec![0, 1].into_iter().collect::<BTreeSet<_>>());
+        let a0 = insert(&mut graph, 0, None, None, init);
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(2));
+        let a2 = insert(&mut graph, 0, Some(&a1), None, Action::Remove(2));
+        let b0 = insert(&mut graph, 1, None, Some(&a2), Action::Add(3));
+        let b1 = insert(&mut graph, 1, Some(&b0), None, Action::Add(2));
+        let actions = |graph: &Graph<u64>| -> Vec<(u64, Action<u64>)> {
+            graph
+                .actions()
+                .into_iter()
+                .map(|a| (*a.creator_id, a.action.clone()))
+                .collect()
+        };
+        let before = actions(&graph);
+        assert_eq!(graph.prune(&a2).unwrap(), 2);
+        assert!(!graph.contains(&a0) && !graph.contains(&a1));
+        assert!(graph.contains(&a2) && graph.contains(&b0));
+        assert_eq!(actions(&graph), before);
+        let actions_from = |lamport, hash| -> Vec<(u64, Action<u64>)> {
+            graph
+                .actions_from(lamport, &0, hash)
+                .into_iter()
+                .map(|a| (*a.creator_id, a.action.clone()))
+                .collect()
+        };
+        assert_eq!(actions_from(1, &a1), before[1..].to_vec());
+        assert_eq!(actions_from(2, &a2), before[2..].to_vec());
+        assert_eq!(graph.get_by_hash(&b1).unwrap().index, 2);
+        assert_eq!(graph.tips().get(&1), Some(&b1));
+        let restored: Graph<u64> = Graph::from_bytes(&graph.to_bytes().unwrap()).unwrap();
+        assert_eq!(actions(&restored), before);
+        match graph.prune(&Hash([0; 32])) {
+            Err(Error::UnknownEvent { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn prune_keeps_sequence_numbers() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(1));
+        let a2 = insert(&mut graph, 0, Some(&a1), None, Action::Add(2));
+        let a3 = insert(&mut graph, 0, Some(&a2), None, Action::Add(3));
+        // The peer holds `a0` and `a1`.
+        let peer_summary: BTreeMap<u64, u64> = vec![(0, 2)].into_iter().collect();
+        let needed = |graph: &Graph<u64>| -> Vec<Hash> {
+            graph
+                .events_for_peer(&peer_summary)
+                .into_iter()
+                .map(|event| event.event.hash().unwrap())
+                .collect()
+        };
+        assert_eq!(needed(&graph), vec![a2.clone(), a3.clone()]);
+        assert_eq!(graph.prune(&a2).unwrap(), 2);
+        assert_eq!(needed(&graph), vec![a2.clone(), a3.clone()]);
+        assert_eq!(graph.summary(), vec![(0, 4)].into_iter().collect());
+        let restored: Graph<u64> = Graph::from_bytes(&graph.to_bytes().unwrap()).unwrap();
+        assert_eq!(needed(&restored), vec![a2, a3]);
+        assert_eq!(restored.summary(), graph.summary());
+    }
+
+    #[test]
+    fn prune_keeps_ancestors_of_fork_heads() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(1));
+        let fork = insert(&mut graph, 0, Some(&a0), None, Action::Add(2));
+        let a2 = insert(&mut graph, 0, Some(&a1), None, Action::Add(3));
+        // The tip of creator 0 is `a2`, but the lower fork head still needs `a0`.
+        assert_eq!(graph.tips().get(&0), Some(&a2));
+        assert_eq!(graph.prune(&a2).unwrap(), 1);
+        assert!(!graph.contains(&a1));
+        assert!(graph.contains(&a0) && graph.contains(&fork));
+    }
+
+    #[test]
+    fn prune_keeps_events_reachable_around_keep_after() {
+        let mut graph = Graph::new();
+        let a0 = insert(&mut graph, 0, None, None, Action::Init(BTreeSet::new()));
+        let a1 = insert(&mut graph, 0, Some(&a0), None, Action::Add(1));
+        // The tip of creator 1 reaches `a0` without passing through `a1`.
+        let b0 = insert(&mut graph, 1, None, Some(&a0), Action::Add(2));
+        assert_eq!(graph.prune(&a1).unwrap(), 0);
+        assert!(graph.contains(&a0) && graph.contains(&b0));
+    }
+
+    #[test]
+    fn strongly_sees_and_rounds() {
+        let mut graph = Graph::new();
+        let init = Action::Init(BTreeSet::new());
+        let a0 = insert(&mut graph, 0, None, None, init.clone());
+        let b0 = insert(&mut graph, 1, None, None, init.clone());
+        let c0 = insert(&mut graph, 2, None, None, init.clone());
+        let d0 = insert(&mut graph, 3, None, None, init);
+        let b1 = insert(&mut graph, 1, Some(&b0), Some(&a0), Action::Add(4));
+        let c1 = insert(&mut graph, 2, Some(&c0), Some(&b1), Action::Add(4));
+        let d1 = insert(&mut graph, 3, Some(&d0), Some(&c1), Action::Add(4));
+        let a1 = insert(&mut graph, 0, Some(&a0), Some(&d1), Action::Add(4));
+        let b2 = insert(&mut graph, 1, Some(&b1), Some(&a1), Action::Add(4));
+        // With four creators, a supermajority is three.
+        assert!(graph.strongly_sees(&d1, &a0, 3));
+        assert!(graph.strongly_sees(&d1, &b0, 3));
+        assert!(!graph.strongly_sees(&d1, &c0, 3));
+        assert!(!graph.strongly_sees(&d1, &d0, 3));
+        assert!(graph.strongly_sees(&a1, &c0, 3));
+        assert!(!graph.strongly_sees(&a1, &d0, 3));
+        assert!(!graph.strongly_sees(&a0, &d1, 1));
+        for hash in &[&a0, &b0, &c0, &d0, &b1, &c1, &d1] {
+            assert_eq!(graph.round(hash, 3), Some(0));
+        }
+        // `a1` strongly sees the round 0 witnesses of `a`, `b` and `c`.
+        assert_eq!(graph.round(&a1, 3), Some(1));
+        assert_eq!(graph.round(&b2, 3), Some(1));
+        assert_eq!(graph.round(&compute_hash(&"unknown").unwrap(), 3), None);
+        // The events of a new creator don't change the rounds of earlier events.
+        insert(&mut graph, 4, None, None, Action::Init(BTreeSet::new()));
+        assert_eq!(graph.round(&a1, 3), Some(1));
+    }
+}
@@ -1,7 +1,9 @@
-use bincode;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+
 use failure::Fail;
 use serde::{Deserialize, Serialize};
-use sha3::{digest::generic_array::transmute, Digest, Sha3_256};
+use sha3::{Digest, Sha3_256};
 
 /// Type of hash commonly used within the library.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -13,20 +15,81 @@ pub enum Error {
     /// A serialization error in `compute_hash`.
     #[fail(display = "Serialization error: {}", _0)]
     ComputeHashSerialize(bincode::Error),
+    /// A hex string of the wrong length in `Hash::from_str`.
+    #[fail(display = "Expected 64 hex digits, found {}", _0)]
+    HexLength(usize),
+    /// A non-hex character in `Hash::from_str`.
+    #[fail(display = "Invalid hex digit: {:?}", _0)]
+    HexDigit(char),
+}
+
+/// Renders the hash as 64 lowercase hex digits.
+impl Display for Hash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a hash from 64 hex digits.
+impl FromStr for Hash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let digits = s
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8).ok_or(Error::HexDigit(c)))
+            .collect::<Result<Vec<u8>, Error>>()?;
+        if digits.len() != 64 {
+            return Err(Error::HexLength(digits.len()));
+        }
+        let mut out = [0u8; 32];
+        for (byte, pair) in out.iter_mut().zip(digits.chunks(2)) {
+            *byte = pair[0] << 4 | pair[1];
+        }
+        Ok(Hash(out))
+    }
+}
+
+/// A hashing algorithm.
+pub trait Hasher: Clone + Debug + Send + Sync + 'static {
+    /// Computes the hash of bytes.
+    fn hash(bytes: &[u8]) -> Hash;
 }
 
-/// Computes the hash of serializable data.
+/// The default hashing algorithm, SHA3-256.
+#[derive(Clone, Debug, Default)]
+pub struct Sha3Hasher;
+
+impl Hasher for Sha3Hasher {
+    fn hash(bytes: &[u8]) -> Hash {
+        let mut hasher = Sha3_256::new();
+        hasher.input(bytes);
+        let r = hasher.result();
+        let mut out = [0u8; 32];
+        out.copy_from_slice(r.as_slice());
+        Hash(out)
+    }
+}
+
+/// Computes the hash of serializable data using the default hashing algorithm.
 pub fn compute_hash<B: Serialize>(b: &B) -> Result<Hash, Error> {
-    let mut hasher = Sha3_256::new();
+    compute_hash_with::<B, Sha3Hasher>(b)
+}
+
+/// Computes the hash of serializable data using the given hashing algorithm.
+pub fn compute_hash_with<B: Serialize, H: Hasher>(b: &B) -> Result<Hash, Error> {
     let ser = bincode::serialize(b).map_err(Error::ComputeHashSerialize)?;
-    hasher.input(ser);
-    let r = hasher.result();
-    Ok(Hash(unsafe { transmute(r) }))
+    Ok(H::hash(&ser))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::compute_hash;
+    use std::str::FromStr;
+
+    use super::{compute_hash, compute_hash_with, Error, Hash, Hasher};
     use sha3::{Digest, Sha3_256};
 
     #[test]
@@ -42,4 +105,43 @@ mod tests {
         // The two hashes should be the same.
         assert_eq!(hash1.0, hash2.as_slice());
     }
+
+    /// A hasher that takes the first bytes of the input.
+    #[derive(Clone, Debug)]
+    struct PrefixHasher;
+
+    impl Hasher for PrefixHasher {
+        fn hash(bytes: &[u8]) -> Hash {
+            let mut out = [0u8; 32];
+            for (o, b) in out.iter_mut().zip(bytes) {
+                *o = *b;
+            }
+            Hash(out)
+        }
+    }
+
+    #[test]
+    fn compute_hash_with_custom_hasher() {
+        let b = 7u64;
+        let hash = compute_hash_with::<_, PrefixHasher>(&b).unwrap();
+        assert_eq!(&hash.0[..8], &bincode::serialize(&b).unwrap()[..]);
+        assert_eq!(&hash.0[8..], &[0u8; 24]);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let h = compute_hash(&"Hash me").unwrap();
+        let s = h.to_string();
+        assert_eq!(s.len(), 64);
+        assert_eq!(s, s.to_lowercase());
+        assert_eq!(Hash::from_str(&s).unwrap(), h);
+        match Hash::from_str(&s[1..]) {
+            Err(Error::HexLength(63)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match Hash::from_str(&s.replacen(|_| true, "g", 1)) {
+            Err(Error::HexDigit('g')) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
 }
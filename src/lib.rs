@@ -4,18 +4,42 @@
 //! `NodeMembership`. The interface connects it with an external networking layer:
 //!
 //! - `NodeMembership::poll` queries the configured local failure detector for any new failures, and
-//! outputs gossip messages for the external networking layer to send to remote nodes.
+//!   outputs gossip messages for the external networking layer to send to remote nodes.
 //!
 //! - `NodeMembership::handle_message` handles a message received by the networking layer from a
-//! remote node.
+//!   remote node.
 //!
 //! The set of currently known group members can be obtained by calling
 //!
 //! - `NodeMembership::group`.
 
+#![forbid(unsafe_code)]
+// `failure_derive` expands into non-local `impl` blocks.
+#![allow(non_local_definitions)]
+
+mod compact_event;
 mod failure_detector;
 mod graph;
 mod hash;
 mod node_membership;
+mod phi_accrual;
+mod signature;
+mod swim;
 
-pub use node_membership::NodeMembership;
+pub use compact_event::{CompactBatch, CompactEvent, Error as CompactError, ParentRef};
+pub use failure_detector::{
+    Clock, Error as FailureDetectorError, FailureDetector, InternalFailureDetector, NodeStatus,
+    SystemClock, DEFAULT_ACTIVITY_TIMEOUT, DEFAULT_SUSPECT_TIMEOUT,
+};
+pub use graph::{
+    Action, ActionRef, AncestorIter, DescendantIter, Error as GraphError, Event, EventRef, Graph,
+    NodeId, SNAPSHOT_VERSION,
+};
+pub use hash::{compute_hash, compute_hash_with, Error as HashError, Hash, Hasher, Sha3Hasher};
+pub use node_membership::{
+    BatchError, ChangeObserver, ChangeRecord, Checkpoint, EmitObserver, Error as MembershipError,
+    MembershipChange, Message, NodeMembership, Outbound, Recipient, ValidationError,
+};
+pub use phi_accrual::PhiAccrualDetector;
+pub use signature::{Signer, Verifier};
+pub use swim::{Probe, SwimDetector};
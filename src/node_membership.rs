@@ -2,85 +2,2422 @@
 //! networking layer:
 //!
 //! - `NodeMembership::poll` queries the configured local failure detector for any new failures, and
-//! outputs gossip messages for the external networking layer to send to remote nodes.
+//!   outputs gossip messages for the external networking layer to send to remote nodes.
 //!
 //! - `NodeMembership::handle_message` handles a message received by the networking layer from a
-//! remote node.
+//!   remote node.
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
 
 use failure::Fail;
+use rand::rngs::StdRng;
+use rand::seq::IteratorRandom;
+use rand::{RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
 
 use crate::failure_detector::{
     Error as FailureDetectorError, FailureDetector, InternalFailureDetector,
 };
-use crate::graph::{Error as GraphError, Event, Graph, NodeId};
+use crate::graph::{Action, ActionRef, Error as GraphError, Event, EventRef, Graph, NodeId};
+use crate::hash::{Hash, Hasher, Sha3Hasher};
+use crate::signature::{Signer, Verifier};
+
+/// The default maximum size of a serialized event in bytes.
+pub const DEFAULT_MAX_EVENT_SIZE: u64 = 64 * 1024;
+
+/// The default maximum number of entries in a piggybacked summary.
+pub const DEFAULT_MAX_SUMMARY_LEN: usize = 64;
+
+/// The default maximum number of recent events in a join response.
+pub const DEFAULT_MAX_JOIN_EVENTS: usize = 256;
+
+/// The default number of peers that each polled message is gossiped to.
+pub const DEFAULT_FANOUT: usize = 3;
 
 /// A node membership error.
 #[derive(Debug, Fail)]
-pub enum Error {
+pub enum Error<N: NodeId> {
     /// A failure detector error.
     #[fail(display = "Failure detector error: {}", _0)]
     FailureDetector(FailureDetectorError),
     /// A gossip graph error.
     #[fail(display = "Gossip graph error: {}", _0)]
-    Graph(GraphError),
+    Graph(GraphError<N>),
+    /// An event validation error.
+    #[fail(display = "Validation error: {}", _0)]
+    Validation(ValidationError),
+    /// The signature of a received event or summary doesn't verify against its creator or sender.
+    #[fail(display = "Bad signature by {:?}", creator)]
+    BadSignature {
+        /// The claimed creator of the event, or sender of the summary.
+        creator: N,
+    },
+    /// The creator of a received event is not a group member.
+    #[fail(display = "Event by non-member {:?}", creator)]
+    NotMember {
+        /// The creator of the event.
+        creator: N,
+    },
+    /// The graph already contains an `Init` event.
+    #[fail(display = "The group is already initialized")]
+    AlreadyInitialized,
+    /// The ID of the local node, required to create events, is not set.
+    #[fail(display = "The local node ID is not set")]
+    NoLocalId,
+    /// The local node has removed itself from the group and can no longer propose changes.
+    #[fail(display = "The local node has left the group")]
+    HasLeft,
+}
+
+/// A violation of the rules that a received event has to satisfy.
+#[derive(Debug, Fail)]
+pub enum ValidationError {
+    /// The serialized event is larger than the configured maximum.
+    #[fail(display = "Event too large: {} bytes", _0)]
+    TooLarge(u64),
+    /// The event could not be serialized in order to find its size.
+    #[fail(display = "Serialization error: {}", _0)]
+    Serialize(bincode::Error),
+    /// The self-parent and the other-parent are the same event.
+    #[fail(display = "Duplicate parent: {}", _0)]
+    DuplicateParent(Hash),
+    /// A parent of the event is not in the graph.
+    #[fail(display = "Missing parent: {}", _0)]
+    MissingParent(Hash),
+    /// The self-parent of the event has a different creator.
+    #[fail(display = "Self-parent has a different creator: {}", _0)]
+    SelfParentCreator(Hash),
+    /// The Lamport timestamp of the event doesn't follow from its parents.
+    #[fail(display = "Invalid Lamport timestamp: {}", _0)]
+    InvalidLamport(u64),
+}
+
+/// An invalid event in a batch.
+#[derive(Debug, Fail)]
+#[fail(display = "Invalid event at position {}: {}", position, error)]
+pub struct BatchError<N: NodeId> {
+    /// The position of the event in the batch.
+    pub position: usize,
+    /// The error caused by the event.
+    pub error: Error<N>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message<N: NodeId> {
     Event(Event<N>),
+    /// An event with a piggybacked summary of the sender's graph.
+    SummarizedEvent {
+        /// The sender of the message.
+        sender: N,
+        /// The gossiped event.
+        event: Event<N>,
+        /// The number of events by each creator that the sender holds.
+        summary: BTreeMap<N, u64>,
+        /// The sender's signature of the sender, the event hash and the summary, if the sender has
+        /// a signer.
+        signature: Option<Vec<u8>>,
+    },
+    /// A request for the events that the sender is missing.
+    SyncRequest {
+        /// The tips of the sender's graph.
+        tips: BTreeMap<N, Hash>,
+    },
+    /// A response to a `SyncRequest`.
+    SyncResponse {
+        /// The events missing from the requester, parents before children.
+        events: Vec<Event<N>>,
+    },
+    /// A request of a joining node to a seed node for the membership state.
+    JoinRequest,
+    /// A response to a `JoinRequest`.
+    JoinResponse {
+        /// The membership state preceding the events.
+        checkpoint: Checkpoint<N>,
+        /// The recent events of the seed, parents before children.
+        events: Vec<Event<N>>,
+    },
+}
+
+/// A summary of the membership state as of some point, from which a node can start without the
+/// event history.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint<N: NodeId> {
+    /// The group members.
+    pub members: BTreeSet<N>,
+    /// The membership epoch.
+    pub epoch: u64,
+    /// The creator and the Lamport timestamp of each event at the frontier of the checkpoint, by
+    /// hash. Events after the checkpoint may have frontier events as parents.
+    pub frontier: BTreeMap<Hash, (N, u64)>,
+    /// The changes of the group up to the checkpoint.
+    pub history: Vec<ChangeRecord<N>>,
+    /// The nodes that removed themselves from the group, with the Lamport timestamp and the hash
+    /// of the event removing each.
+    pub departed: BTreeMap<N, (u64, Hash)>,
+    /// The number of events by each creator up to the checkpoint.
+    pub summary: BTreeMap<N, u64>,
+}
+
+/// A record of a node joining or leaving the group.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeRecord<N: NodeId> {
+    /// The change: either `Add` or `Remove` of a node.
+    pub action: Action<N>,
+    /// The creator of the event that made the change.
+    pub creator_id: N,
+    /// The hash of the event that made the change.
+    pub hash: Hash,
+    /// The Lamport timestamp of the event.
+    pub lamport: u64,
+    /// The epoch resulting from the change.
+    pub epoch: u64,
+}
+
+/// The intended recipient of an outgoing message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Recipient<N: NodeId> {
+    /// All remote nodes.
+    All,
+    /// A single remote node.
+    Node(N),
+    /// The remote node that sent the request being answered.
+    Requester,
+    /// The given remote nodes.
+    Peers(Vec<N>),
+}
+
+/// An outgoing message together with the peers to send it to.
+#[derive(Clone, Debug)]
+pub struct Outbound<N: NodeId> {
+    /// The peers to send the message to.
+    pub recipients: Vec<N>,
+    /// The message.
+    pub message: Message<N>,
+}
+
+/// An observer of outgoing messages.
+pub type EmitObserver<N> = Box<dyn FnMut(&Recipient<N>, &Message<N>)>;
+
+/// A change of the group membership.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MembershipChange<N: NodeId> {
+    /// The nodes that joined the group.
+    pub added: BTreeSet<N>,
+    /// The nodes that left the group.
+    pub removed: BTreeSet<N>,
 }
 
-unsafe impl<N: NodeId> Send for Message<N> {}
-unsafe impl<N: NodeId> Sync for Message<N> {}
+/// An observer of membership changes.
+pub type ChangeObserver<N> = Box<dyn FnMut(&MembershipChange<N>)>;
 
-/// The state of node group membership.
-pub struct NodeMembership<N: NodeId> {
+/// The state of node group membership, with events hashed by `H`.
+pub struct NodeMembership<N: NodeId, H: Hasher = Sha3Hasher> {
+    /// The ID of the local node, which creates the local events.
+    our_id: Option<N>,
+    /// The checkpoint from which the state was restored, preceding all events in the graph.
+    base: Option<Checkpoint<N>>,
+    /// The signer of the local events.
+    signer: Option<Box<dyn Signer>>,
+    /// The verifiers of the events of remote nodes.
+    verifiers: BTreeMap<N, Box<dyn Verifier>>,
     /// The gossip graph local to this node.
-    graph: Graph<N>,
+    graph: Graph<N, H>,
     /// The failure detector subsystem.
     failure_detector: Box<dyn FailureDetector<N>>,
+    /// The maximum size of a serialized event accepted from a remote node.
+    max_event_size: u64,
+    /// Observers of outgoing messages.
+    emit_observers: Vec<EmitObserver<N>>,
+    /// Observers of membership changes.
+    change_observers: Vec<ChangeObserver<N>>,
+    /// The group as of the last notification of the change observers.
+    notified_group: BTreeSet<N>,
+    /// The known summary of each peer, mapping creators to the number of their events the peer
+    /// holds.
+    peer_summaries: BTreeMap<N, BTreeMap<N, u64>>,
+    /// The maximum number of entries in a piggybacked summary.
+    max_summary_len: usize,
+    /// The maximum number of recent events in a join response.
+    max_join_events: usize,
+    /// The group, as of the last change of the graph.
+    members: BTreeSet<N>,
+    /// The membership epoch, as of the last change of the graph.
+    epoch: u64,
+    /// The changes of the group, from the base checkpoint and the graph.
+    history: Vec<ChangeRecord<N>>,
+    /// The nodes that removed themselves from the group, from the base checkpoint and the graph.
+    departed: BTreeMap<N, (u64, Hash)>,
+    /// The number of peers that each polled message is gossiped to.
+    fanout: usize,
+    /// The source of randomness for peer selection.
+    rng: Box<dyn RngCore>,
 }
 
-impl<N: NodeId + 'static> Default for NodeMembership<N> {
+impl<N: NodeId, H: Hasher> Default for NodeMembership<N, H> {
     fn default() -> Self {
         NodeMembership {
+            our_id: None,
+            base: None,
+            signer: None,
+            verifiers: BTreeMap::new(),
             graph: Graph::new(),
             failure_detector: Box::new(InternalFailureDetector::new()),
+            max_event_size: DEFAULT_MAX_EVENT_SIZE,
+            emit_observers: Vec::new(),
+            change_observers: Vec::new(),
+            notified_group: BTreeSet::new(),
+            peer_summaries: BTreeMap::new(),
+            max_summary_len: DEFAULT_MAX_SUMMARY_LEN,
+            max_join_events: DEFAULT_MAX_JOIN_EVENTS,
+            members: BTreeSet::new(),
+            epoch: 0,
+            history: Vec::new(),
+            departed: BTreeMap::new(),
+            fanout: DEFAULT_FANOUT,
+            rng: Box::new(StdRng::from_entropy()),
         }
     }
 }
 
-impl<N: NodeId + 'static> NodeMembership<N> {
-    /// Constructs a new state of group membership.
-    pub fn new() -> NodeMembership<N> {
+impl<N: NodeId, H: Hasher> NodeMembership<N, H> {
+    /// Constructs a new state of group membership. The default `InternalFailureDetector` has no
+    /// timeouts, so it never reports failures.
+    pub fn new() -> NodeMembership<N, H> {
         NodeMembership::default()
     }
 
-    pub fn graph(&self) -> &Graph<N> {
+    /// Constructs a new state of group membership with a custom failure detector.
+    pub fn with_failure_detector(failure_detector: Box<dyn FailureDetector<N>>) -> Self {
+        NodeMembership {
+            failure_detector,
+            ..NodeMembership::default()
+        }
+    }
+
+    /// Constructs a new state of group membership of the local node with the given ID.
+    pub fn with_our_id(our_id: N) -> Self {
+        NodeMembership {
+            our_id: Some(our_id),
+            ..NodeMembership::default()
+        }
+    }
+
+    /// Sets the ID of the local node.
+    pub fn set_our_id(&mut self, our_id: N) {
+        self.our_id = Some(our_id);
+    }
+
+    /// Constructs a state of group membership from a checkpoint. The restored state has the group
+    /// of the checkpoint and accepts events with parents at the checkpoint frontier.
+    pub fn restore(checkpoint: Checkpoint<N>) -> Self {
+        NodeMembership {
+            members: checkpoint.members.clone(),
+            epoch: checkpoint.epoch,
+            history: checkpoint.history.clone(),
+            departed: checkpoint.departed.clone(),
+            graph: Graph::with_base_summary(checkpoint.summary.clone()),
+            base: Some(checkpoint),
+            ..NodeMembership::default()
+        }
+    }
+
+    /// Takes a checkpoint of the current membership state.
+    pub fn checkpoint(&self) -> Checkpoint<N> {
+        let frontier = self
+            .tips()
+            .into_values()
+            .filter_map(|hash| {
+                let (creator, lamport) = self.parent_info(&self.graph, &hash)?;
+                Some((hash, (creator.clone(), lamport)))
+            })
+            .collect();
+        Checkpoint {
+            members: self.members.clone(),
+            epoch: self.epoch,
+            frontier,
+            history: self.history.clone(),
+            departed: self.departed.clone(),
+            summary: self.graph.summary(),
+        }
+    }
+
+    /// Prunes the events of the graph preceding `keep_after`, as `Graph::prune` does, and returns
+    /// the number of removed events. The group, the epoch and the history are unchanged.
+    pub fn prune(&mut self, keep_after: &Hash) -> Result<usize, Error<N>> {
+        self.graph.prune(keep_after).map_err(Error::Graph)
+    }
+
+    /// Sets the signer of the local events.
+    pub fn set_signer(&mut self, signer: Box<dyn Signer>) {
+        self.signer = Some(signer);
+    }
+
+    /// Reports that a remote node has been heard from, for example through a heartbeat of the
+    /// networking layer, to the failure detector. The creators of received events are reported
+    /// automatically. A failure detector with timeouts relies on the caller to report the
+    /// activity of quiet members.
+    pub fn record_activity(&mut self, node: &N) {
+        self.failure_detector.record_activity(node);
+    }
+
+    /// Sets the verifier of the events created by a remote node. Once a node has a verifier, its
+    /// events are only accepted with a valid signature.
+    pub fn set_verifier(&mut self, node: N, verifier: Box<dyn Verifier>) {
+        self.verifiers.insert(node, verifier);
+    }
+
+    pub fn graph(&self) -> &Graph<N, H> {
         &self.graph
     }
 
+    /// Sets the maximum size of a serialized event accepted from a remote node.
+    pub fn set_max_event_size(&mut self, max_event_size: u64) {
+        self.max_event_size = max_event_size;
+    }
+
+    /// Sets the maximum number of entries in a piggybacked summary.
+    pub fn set_max_summary_len(&mut self, max_summary_len: usize) {
+        self.max_summary_len = max_summary_len;
+    }
+
+    /// Sets the number of peers that each polled message is gossiped to.
+    pub fn set_fanout(&mut self, fanout: usize) {
+        self.fanout = fanout;
+    }
+
+    /// Sets the source of randomness for peer selection, which is seeded from entropy by default.
+    pub fn set_rng(&mut self, rng: Box<dyn RngCore>) {
+        self.rng = rng;
+    }
+
+    /// Sets the maximum number of recent events in a join response.
+    pub fn set_max_join_events(&mut self, max_join_events: usize) {
+        self.max_join_events = max_join_events;
+    }
+
+    /// Piggybacks a summary of the local graph on an event, bounded by the maximum summary length.
+    /// The summary entries of creators with the most events in the graph are kept. The summary is
+    /// signed if a signer is set.
+    pub fn summarize_event(&self, sender: N, event: Event<N>) -> Result<Message<N>, Error<N>> {
+        let mut entries: Vec<(N, u64)> = self.graph.summary().into_iter().collect();
+        entries.sort_by(|(_, a), (_, b)| b.cmp(a));
+        entries.truncate(self.max_summary_len);
+        let summary: BTreeMap<N, u64> = entries.into_iter().collect();
+        let signature = match &self.signer {
+            Some(signer) => Some(signer.sign(&Self::summary_bytes(&sender, &event, &summary)?)),
+            None => None,
+        };
+        Ok(Message::SummarizedEvent {
+            sender,
+            event,
+            summary,
+            signature,
+        })
+    }
+
+    /// Serializes the signed part of a summarized event: the sender, the event hash and the
+    /// summary.
+    fn summary_bytes(
+        sender: &N,
+        event: &Event<N>,
+        summary: &BTreeMap<N, u64>,
+    ) -> Result<Vec<u8>, Error<N>> {
+        let hash = event
+            .hash_with::<H>()
+            .map_err(|e| Error::Graph(GraphError::Hash(e)))?;
+        bincode::serialize(&(sender, hash, summary))
+            .map_err(|e| Error::Validation(ValidationError::Serialize(e)))
+    }
+
+    /// Verifies the signature of a summary if there is a verifier for its sender.
+    fn verify_summary(
+        &self,
+        sender: &N,
+        event: &Event<N>,
+        summary: &BTreeMap<N, u64>,
+        signature: Option<&Vec<u8>>,
+    ) -> Result<(), Error<N>> {
+        let verifier = match self.verifiers.get(sender) {
+            Some(verifier) => verifier,
+            None => return Ok(()),
+        };
+        let bytes = Self::summary_bytes(sender, event, summary)?;
+        match signature {
+            Some(signature) if verifier.verify(&bytes, signature) => Ok(()),
+            _ => Err(Error::BadSignature {
+                creator: sender.clone(),
+            }),
+        }
+    }
+
+    /// Registers an observer of outgoing messages.
+    ///
+    /// The observer is a tap rather than a filter: it fires exactly once for every message
+    /// returned by `poll` or `handle_message`, before the message is returned, and cannot modify
+    /// it. Messages returned by `poll` are observed with the peers chosen to receive them.
+    pub fn on_emit(&mut self, cb: EmitObserver<N>) {
+        self.emit_observers.push(cb);
+    }
+
+    /// Registers an observer of membership changes.
+    ///
+    /// The observer fires from the calls that update the graph, such as `handle_message` and
+    /// `poll`, whenever the group differs from the group at the previous notification. It is
+    /// passed the nodes added and removed in between.
+    pub fn on_change(&mut self, cb: ChangeObserver<N>) {
+        if self.change_observers.is_empty() {
+            self.notified_group = self.members.clone();
+        }
+        self.change_observers.push(cb);
+    }
+
+    /// Records a summary received from a peer, or acknowledging events delivered to it.
+    ///
+    /// The known summary of the peer is updated to the maximum of the known and the given number
+    /// of events for each creator, since a peer never loses events.
+    pub fn record_peer_summary(&mut self, peer: N, summary: BTreeMap<N, u64>) {
+        let known = self.peer_summaries.entry(peer).or_default();
+        for (creator, count) in summary {
+            match known.entry(creator) {
+                Entry::Vacant(entry) => {
+                    entry.insert(count);
+                }
+                Entry::Occupied(mut entry) => {
+                    let known_count = entry.get_mut();
+                    *known_count = count.max(*known_count);
+                }
+            }
+        }
+    }
+
+    /// Creates a request for the events of a remote node that are missing locally. The request is
+    /// passed to the emit observers with the peer as the recipient.
+    pub fn sync_request(&mut self, peer: N) -> Message<N> {
+        let msg = Message::SyncRequest { tips: self.tips() };
+        self.observe(&Recipient::Node(peer), &msg);
+        msg
+    }
+
+    /// Creates a request to a seed node for the membership state, to be sent by a node with an
+    /// empty graph that wants to join the group. The request is passed to the emit observers with
+    /// the seed as the recipient.
+    ///
+    /// Handling the seed's `JoinResponse` makes the group known locally, but the local node only
+    /// becomes a member once an `Add` proposal for it is accepted by the group.
+    pub fn request_join(&mut self, seed: N) -> Message<N> {
+        let msg = Message::JoinRequest;
+        self.observe(&Recipient::Node(seed), &msg);
+        msg
+    }
+
+    /// Creates a response to a `JoinRequest`: a checkpoint of the state preceding at most the
+    /// configured maximum number of recent events, together with those events.
+    fn join_response(&self) -> Message<N> {
+        let (actions, older, recent) = self.graph.split_recent(self.max_join_events);
+        let (mut members, mut epoch, mut history, mut departed) = self.base_state();
+        Self::replay(
+            &mut members,
+            &mut epoch,
+            &mut history,
+            &mut departed,
+            actions,
+        );
+        let mut summary = self.graph.summary();
+        for event in &recent {
+            if let Some(count) = summary.get_mut(event.creator_id()) {
+                *count -= 1;
+            }
+        }
+        summary.retain(|_, count| *count > 0);
+        let recent_hashes: BTreeSet<&Hash> = recent
+            .iter()
+            .filter_map(|event| self.graph.get_hash(event.index))
+            .collect();
+        // The latest older event or base frontier event of each creator, so that the joiner has
+        // the same tips as the seed for creators without recent events.
+        let mut latest: BTreeMap<&N, (&Hash, u64)> = BTreeMap::new();
+        let base_frontier = self
+            .base
+            .iter()
+            .flat_map(|base| base.frontier.iter())
+            .map(|(hash, (creator, lamport))| (hash, creator, *lamport));
+        let older_events = older.iter().filter_map(|event| {
+            let hash = self.graph.get_hash(event.index)?;
+            Some((hash, event.creator_id(), event.lamport()))
+        });
+        for (hash, creator, lamport) in base_frontier.chain(older_events) {
+            match latest.entry(creator) {
+                Entry::Vacant(entry) => {
+                    entry.insert((hash, lamport));
+                }
+                Entry::Occupied(mut entry) => {
+                    if lamport >= entry.get().1 {
+                        entry.insert((hash, lamport));
+                    }
+                }
+            }
+        }
+        let mut frontier: BTreeMap<Hash, (N, u64)> = latest
+            .into_iter()
+            .map(|(creator, (hash, lamport))| (hash.clone(), (creator.clone(), lamport)))
+            .collect();
+        // The parents of recent events that are not recent themselves.
+        for event in &recent {
+            for hash in event.self_parent().into_iter().chain(event.other_parent()) {
+                if recent_hashes.contains(hash) {
+                    continue;
+                }
+                if let Some((creator, lamport)) = self.parent_info(&self.graph, hash) {
+                    frontier.insert(hash.clone(), (creator.clone(), lamport));
+                }
+            }
+        }
+        Message::JoinResponse {
+            checkpoint: Checkpoint {
+                members,
+                epoch,
+                frontier,
+                history,
+                departed,
+                summary,
+            },
+            events: recent
+                .into_iter()
+                .map(|event| event.event.clone())
+                .collect(),
+        }
+    }
+
+    /// Handles a `JoinResponse` by taking its checkpoint as the base and inserting its events.
+    /// The events are only validated against their parents, since the local node doesn't know the
+    /// group of their creators before the response.
+    fn handle_join_response(
+        &mut self,
+        checkpoint: &Checkpoint<N>,
+        events: &[Event<N>],
+    ) -> Result<(), Error<N>> {
+        if self.base.is_some() || !self.graph.actions().is_empty() {
+            return Err(Error::AlreadyInitialized);
+        }
+        self.base = Some(checkpoint.clone());
+        self.members = checkpoint.members.clone();
+        self.epoch = checkpoint.epoch;
+        self.history = checkpoint.history.clone();
+        self.departed = checkpoint.departed.clone();
+        self.graph = Graph::with_base_summary(checkpoint.summary.clone());
+        for event in events {
+            self.validate_event(event).map_err(Error::Validation)?;
+            self.verify_signature(event)?;
+            let index = self
+                .graph
+                .insert_checked(event.clone())
+                .map_err(Error::Graph)?
+                .index;
+            self.update_state(index);
+        }
+        Ok(())
+    }
+
+    /// Returns the dependency-complete set of events that the peer still needs according to its
+    /// known summary. Without a recorded summary, that is the whole graph.
+    ///
+    /// The known summary of a peer is updated by `record_peer_summary` and by summaries
+    /// piggybacked on events received from that peer.
+    pub fn events_undelivered_to(&self, peer: &N) -> Vec<EventRef<'_, N>> {
+        match self.peer_summaries.get(peer) {
+            Some(summary) => self.graph.events_for_peer(summary),
+            None => self.graph.events_for_peer(&BTreeMap::new()),
+        }
+    }
+
+    /// Validates an event against the full rule set and returns the first violation.
+    ///
+    /// The checks run in the following order:
+    ///
+    /// 1. the serialized event is no larger than the configured maximum size,
+    /// 2. the self-parent and the other-parent are distinct,
+    /// 3. the self-parent and then the other-parent are present in the graph,
+    /// 4. the self-parent has the same creator as the event,
+    /// 5. the Lamport timestamp is one more than the greatest timestamp of the parents, or 0
+    ///    without parents.
+    ///
+    /// On insertion, an event that passes these checks then has its signature verified against
+    /// the verifier of its creator, failing with `BadSignature`, and only then is the membership
+    /// of its creator checked, failing with `NotMember` or `AlreadyInitialized`.
+    pub fn validate_event(&self, event: &Event<N>) -> Result<(), ValidationError> {
+        self.validate_event_in(&self.graph, event)
+    }
+
+    /// Validates a batch of events as if they were inserted in order, without mutating the state.
+    ///
+    /// Each event is checked as on insertion, including its signature and the membership of its
+    /// creator, against a temporary copy of the graph that contains the preceding events of the
+    /// batch. The first offending event is reported together with its position in the batch.
+    pub fn validate_batch(&self, events: &[Event<N>]) -> Result<(), BatchError<N>> {
+        let mut graph = self.graph.clone();
+        for (position, event) in events.iter().enumerate() {
+            let batch_error = |error| BatchError { position, error };
+            let hash = event
+                .hash_with::<H>()
+                .map_err(|e| batch_error(Error::Graph(GraphError::Hash(e))))?;
+            if graph.contains(&hash) {
+                continue;
+            }
+            self.check_event(&graph, event).map_err(batch_error)?;
+            graph
+                .insert_checked(event.clone())
+                .map_err(|e| batch_error(Error::Graph(e)))?;
+        }
+        Ok(())
+    }
+
+    /// Checks an event before its insertion into the given graph: the event has to be valid and
+    /// correctly signed, and its creator has to be a member of the group as of the causal past of
+    /// the event. The only exception is the genesis `Init` event, which has no parents and is only
+    /// accepted before the group is initialized. Any other `Init` event fails with
+    /// `AlreadyInitialized`.
+    fn check_event(&self, graph: &Graph<N, H>, event: &Event<N>) -> Result<(), Error<N>> {
+        self.validate_event_in(graph, event)
+            .map_err(Error::Validation)?;
+        self.verify_signature(event)?;
+        if let Action::Init(_) = event.action() {
+            let genesis = event.self_parent().is_none() && event.other_parent().is_none();
+            if !genesis || self.is_initialized(graph) {
+                return Err(Error::AlreadyInitialized);
+            }
+            return Ok(());
+        }
+        if !self.group_before(graph, event).contains(event.creator_id()) {
+            return Err(Error::NotMember {
+                creator: event.creator_id().clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks whether the group is initialized: whether there is a base checkpoint or the given
+    /// graph has an `Init` event.
+    fn is_initialized(&self, graph: &Graph<N, H>) -> bool {
+        self.base.is_some()
+            || graph
+                .actions()
+                .iter()
+                .any(|a| matches!(a.action, Action::Init(_)))
+    }
+
+    /// Validates an event against the given graph.
+    fn validate_event_in(
+        &self,
+        graph: &Graph<N, H>,
+        event: &Event<N>,
+    ) -> Result<(), ValidationError> {
+        let size = bincode::serialized_size(event).map_err(ValidationError::Serialize)?;
+        if size > self.max_event_size {
+            return Err(ValidationError::TooLarge(size));
+        }
+        if let (Some(self_parent), Some(other_parent)) = (event.self_parent(), event.other_parent())
+        {
+            if self_parent == other_parent {
+                return Err(ValidationError::DuplicateParent(self_parent.clone()));
+            }
+        }
+        let mut lamport = 0;
+        for hash in event
+            .self_parent()
+            .iter()
+            .chain(event.other_parent().iter())
+        {
+            match self.parent_info(graph, hash) {
+                Some((_, parent_lamport)) => lamport = lamport.max(parent_lamport + 1),
+                None => return Err(ValidationError::MissingParent((*hash).clone())),
+            }
+        }
+        if let Some(hash) = event.self_parent() {
+            if let Some((creator_id, _)) = self.parent_info(graph, hash) {
+                if creator_id != event.creator_id() {
+                    return Err(ValidationError::SelfParentCreator(hash.clone()));
+                }
+            }
+        }
+        if event.lamport() != lamport {
+            return Err(ValidationError::InvalidLamport(event.lamport()));
+        }
+        Ok(())
+    }
+
+    /// Gets the creator and the Lamport timestamp of a possible parent event, which is either in
+    /// the graph or at the frontier of the base checkpoint.
+    fn parent_info<'a>(&'a self, graph: &'a Graph<N, H>, hash: &Hash) -> Option<(&'a N, u64)> {
+        if let Some(event) = graph.get_by_hash(hash) {
+            return Some((event.event.creator_id(), event.lamport()));
+        }
+        self.base
+            .as_ref()
+            .and_then(|base| base.frontier.get(hash))
+            .map(|(creator, lamport)| (creator, *lamport))
+    }
+
+    /// Gets the tips of the graph, falling back to the latest frontier event of the base
+    /// checkpoint for creators without events in the graph.
+    fn tips(&self) -> BTreeMap<N, Hash> {
+        let mut latest: BTreeMap<N, (u64, Hash)> = BTreeMap::new();
+        for (hash, (creator, lamport)) in self.base.iter().flat_map(|base| base.frontier.iter()) {
+            if latest
+                .get(creator)
+                .is_none_or(|(latest_lamport, _)| lamport >= latest_lamport)
+            {
+                latest.insert(creator.clone(), (*lamport, hash.clone()));
+            }
+        }
+        let mut tips: BTreeMap<N, Hash> = latest
+            .into_iter()
+            .map(|(creator, (_, hash))| (creator, hash))
+            .collect();
+        tips.extend(self.graph.tips());
+        tips
+    }
+
     /// Polls the failure detector for any new failures and outputs messages for the networking
     /// layer to send to remote nodes.
-    pub fn poll(&mut self) -> Result<Vec<Message<N>>, Error> {
+    ///
+    /// Each failed group member is removed by a local `Remove` event, which is output for gossip.
+    /// Failures of nodes that are not members are ignored, and so are all failures while the local
+    /// node is not a member, such as once it has left the group.
+    ///
+    /// Each message is tagged with the configured fanout of randomly chosen members other than
+    /// the local node, or with all of them if the group is smaller than that.
+    pub fn poll(&mut self) -> Result<Vec<Outbound<N>>, Error<N>> {
         self.failure_detector
             .poll_failures()
             .map_err(Error::FailureDetector)?;
-        let _failures = self.failure_detector.dequeue_failures();
-        // TODO: emit events
-        Ok(Vec::new())
+        let mut messages = Vec::new();
+        for node in self.failure_detector.dequeue_failures() {
+            let not_member = match &self.our_id {
+                Some(our_id) => !self.members.contains(our_id),
+                None => false,
+            };
+            if self.has_left() || not_member {
+                break;
+            }
+            if self.members.contains(&node) {
+                let event = self.create_event(Action::Remove(node))?;
+                messages.push(Message::Event(event));
+            }
+        }
+        self.notify_change();
+        let peers: Vec<N> = self
+            .members
+            .iter()
+            .filter(|node| Some(*node) != self.our_id.as_ref())
+            .cloned()
+            .collect();
+        let outbound: Vec<Outbound<N>> = messages
+            .into_iter()
+            .map(|message| {
+                let mut recipients = peers
+                    .iter()
+                    .cloned()
+                    .choose_multiple(&mut self.rng, self.fanout);
+                recipients.sort();
+                Outbound {
+                    recipients,
+                    message,
+                }
+            })
+            .collect();
+        for out in &outbound {
+            self.observe(&Recipient::Peers(out.recipients.clone()), &out.message);
+        }
+        Ok(outbound)
     }
 
     /// Handles an incoming message from the networking layer.
-    pub fn handle_message(&mut self, _msg: &Message<N>) -> Result<Vec<Message<N>>, Error> {
-        // FIXME
-        Ok(Vec::new())
+    ///
+    /// A received event is validated and inserted into the graph. The event is output for
+    /// forwarding to other peers unless it was already known, in which case nothing is output.
+    /// The graph serves as the set of seen events, so each event is forwarded at most once.
+    /// Events by nodes that are not group members as of the causal past of the event are rejected,
+    /// except for the genesis `Init` event of an uninitialized group.
+    ///
+    /// A summary piggybacked on an event updates the known summary of the sender unless it has
+    /// more entries than the configured maximum, in which case it is ignored. It is only recorded
+    /// once the event is accepted. Once the sender has a verifier, the message is only accepted
+    /// with a valid signature of the summary.
+    ///
+    /// A sync request is answered with a sync response, to be sent back to the requester, holding
+    /// the local events the requester is missing. The events of a sync response are validated and
+    /// inserted in order but not output for forwarding.
+    ///
+    /// A join request is answered with a join response, to be sent back to the requester. A join
+    /// response is only accepted by a node with an empty graph and no base checkpoint, and
+    /// otherwise fails with `AlreadyInitialized`.
+    pub fn handle_message(&mut self, msg: &Message<N>) -> Result<Vec<Message<N>>, Error<N>> {
+        let mut recipient = Recipient::All;
+        let output = match msg {
+            Message::Event(event) => self.handle_event(event)?,
+            Message::SummarizedEvent {
+                sender,
+                event,
+                summary,
+                signature,
+            } => {
+                self.verify_summary(sender, event, summary, signature.as_ref())?;
+                let output = self.handle_event(event)?;
+                if summary.len() <= self.max_summary_len {
+                    self.record_peer_summary(sender.clone(), summary.clone());
+                }
+                output
+            }
+            Message::SyncRequest { tips } => {
+                recipient = Recipient::Requester;
+                vec![Message::SyncResponse {
+                    events: self
+                        .graph
+                        .events_missing_from(tips)
+                        .into_iter()
+                        .map(|event| event.event.clone())
+                        .collect(),
+                }]
+            }
+            Message::SyncResponse { events } => {
+                for event in events {
+                    self.insert_event(event)?;
+                }
+                Vec::new()
+            }
+            Message::JoinRequest => {
+                recipient = Recipient::Requester;
+                vec![self.join_response()]
+            }
+            Message::JoinResponse { checkpoint, events } => {
+                self.handle_join_response(checkpoint, events)?;
+                Vec::new()
+            }
+        };
+        Ok(self.emit_to(&recipient, output))
+    }
+
+    /// Handles an event received from a remote node.
+    fn handle_event(&mut self, event: &Event<N>) -> Result<Vec<Message<N>>, Error<N>> {
+        if self.insert_event(event)? {
+            Ok(vec![Message::Event(event.clone())])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Validates and inserts an event unless it is already known. Except for the genesis `Init`
+    /// event, the creator has to be a member of the group as of the causal past of the event, so
+    /// that the outcome doesn't depend on the order in which concurrent events arrive. The failure
+    /// detector is told about the activity of a remote creator. Returns whether the event was
+    /// inserted.
+    fn insert_event(&mut self, event: &Event<N>) -> Result<bool, Error<N>> {
+        let hash = event
+            .hash_with::<H>()
+            .map_err(|e| Error::Graph(GraphError::Hash(e)))?;
+        if self.graph.contains(&hash) {
+            return Ok(false);
+        }
+        self.check_event(&self.graph, event)?;
+        let index = self
+            .graph
+            .insert_checked(event.clone())
+            .map_err(Error::Graph)?
+            .index;
+        self.update_state(index);
+        if self.our_id.as_ref() != Some(event.creator_id()) {
+            self.failure_detector.record_activity(event.creator_id());
+        }
+        Ok(true)
+    }
+
+    /// Creates the genesis event that registers the initial group members. Returns the event to
+    /// broadcast.
+    pub fn init(&mut self, members: BTreeSet<N>) -> Result<Vec<Message<N>>, Error<N>> {
+        let our_id = self.our_id.clone().ok_or(Error::NoLocalId)?;
+        if self.is_initialized(&self.graph) {
+            return Err(Error::AlreadyInitialized);
+        }
+        let event = self.insert_local(Event::new(our_id, None, None, 0, Action::Init(members)))?;
+        Ok(self.emit(vec![Message::Event(event)]))
     }
 
-    /// Returns the currently known group members.
+    /// Proposes to add a node to the group. Returns the event to gossip, or nothing if the node
+    /// is already a member. Fails once the local node has left the group.
+    pub fn propose_add(&mut self, node: N) -> Result<Vec<Message<N>>, Error<N>> {
+        if self.has_left() {
+            return Err(Error::HasLeft);
+        }
+        if self.members.contains(&node) {
+            return Ok(Vec::new());
+        }
+        let event = self.create_event(Action::Add(node))?;
+        Ok(self.emit(vec![Message::Event(event)]))
+    }
+
+    /// Proposes to remove a node from the group. Returns the event to gossip, or nothing if the
+    /// node is not a member. Fails once the local node has left the group.
+    pub fn propose_remove(&mut self, node: N) -> Result<Vec<Message<N>>, Error<N>> {
+        if self.has_left() {
+            return Err(Error::HasLeft);
+        }
+        if !self.members.contains(&node) {
+            return Ok(Vec::new());
+        }
+        let event = self.create_event(Action::Remove(node))?;
+        Ok(self.emit(vec![Message::Event(event)]))
+    }
+
+    /// Announces that the local node leaves the group. Returns the event removing the local node
+    /// to gossip, or nothing if the local node is not a member. Leaving is final.
+    pub fn leave(&mut self) -> Result<Vec<Message<N>>, Error<N>> {
+        let our_id = self.our_id.clone().ok_or(Error::NoLocalId)?;
+        if self.has_left() {
+            return Err(Error::HasLeft);
+        }
+        if !self.members.contains(&our_id) {
+            return Ok(Vec::new());
+        }
+        let event = self.create_event(Action::Remove(our_id))?;
+        Ok(self.emit(vec![Message::Event(event)]))
+    }
+
+    /// Checks whether the local node has removed itself from the group.
+    fn has_left(&self) -> bool {
+        self.our_id
+            .as_ref()
+            .is_some_and(|our_id| self.departed.contains_key(our_id))
+    }
+
+    /// Creates a local event with the given action and inserts it into the graph. The self-parent
+    /// is the tip of the local node and the other-parent is the most recently inserted tip of
+    /// another creator. Fails with `NotMember` unless the local node is a member of the group as
+    /// of the causal past of the event, since remote nodes would reject the event.
+    fn create_event(&mut self, action: Action<N>) -> Result<Event<N>, Error<N>> {
+        let our_id = self.our_id.clone().ok_or(Error::NoLocalId)?;
+        let mut tips = self.tips();
+        let self_parent = tips.remove(&our_id);
+        let other_parent = tips.into_values().max_by_key(|hash| {
+            self.parent_info(&self.graph, hash)
+                .map(|(_, lamport)| (self.graph.get_index(hash), lamport))
+        });
+        let lamport = self_parent
+            .iter()
+            .chain(other_parent.iter())
+            .filter_map(|hash| self.parent_info(&self.graph, hash))
+            .map(|(_, lamport)| lamport + 1)
+            .max()
+            .unwrap_or(0);
+        let event = Event::new(our_id.clone(), self_parent, other_parent, lamport, action);
+        if !self.group_before(&self.graph, &event).contains(&our_id) {
+            return Err(Error::NotMember { creator: our_id });
+        }
+        self.insert_local(event)
+    }
+
+    /// Computes the group as of the causal past of an event: the group of the base checkpoint
+    /// changed by the actions of the pruned events and of the parents of the event and their
+    /// ancestors in the given graph.
+    fn group_before(&self, graph: &Graph<N, H>, event: &Event<N>) -> BTreeSet<N> {
+        let (mut members, mut departed) = match &self.base {
+            Some(base) => (base.members.clone(), base.departed.clone()),
+            None => (BTreeSet::new(), BTreeMap::new()),
+        };
+        let parents = event.self_parent().into_iter().chain(event.other_parent());
+        Self::replay(
+            &mut members,
+            &mut 0,
+            &mut Vec::new(),
+            &mut departed,
+            graph.causal_actions(parents),
+        );
+        members
+    }
+
+    /// Verifies the signature of an event if there is a verifier for its creator.
+    fn verify_signature(&self, event: &Event<N>) -> Result<(), Error<N>> {
+        let verifier = match self.verifiers.get(event.creator_id()) {
+            Some(verifier) => verifier,
+            None => return Ok(()),
+        };
+        let bytes = event
+            .signed_bytes()
+            .map_err(|e| Error::Validation(ValidationError::Serialize(e)))?;
+        match event.signature() {
+            Some(signature) if verifier.verify(&bytes, signature) => Ok(()),
+            _ => Err(Error::BadSignature {
+                creator: event.creator_id().clone(),
+            }),
+        }
+    }
+
+    /// Signs a local event if a signer is set and inserts it into the graph.
+    fn insert_local(&mut self, mut event: Event<N>) -> Result<Event<N>, Error<N>> {
+        if let Some(signer) = &self.signer {
+            let bytes = event
+                .signed_bytes()
+                .map_err(|e| Error::Validation(ValidationError::Serialize(e)))?;
+            event = event.with_signature(signer.sign(&bytes));
+        }
+        let index = self
+            .graph
+            .insert_checked(event.clone())
+            .map_err(Error::Graph)?
+            .index;
+        self.update_state(index);
+        Ok(event)
+    }
+
+    /// Returns the currently known group members in ascending order.
     pub fn group(&self) -> Vec<N> {
-        // FIXME
-        Vec::new()
+        self.members.iter().cloned().collect()
+    }
+
+    /// Checks whether a node is a group member.
+    pub fn is_member(&self, node: &N) -> bool {
+        self.members.contains(node)
+    }
+
+    /// Returns the number of group members.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Checks whether the group has no members.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Returns the record of every change of the group in the topological order of the events
+    /// that made them. The part preceding a checkpoint is kept in the checkpoint.
+    ///
+    /// Records are appended as events arrive. An event that arrives late and is ordered before
+    /// some recorded changes, such as an event concurrent with them, rewrites the records and the
+    /// epochs from its position on.
+    pub fn history(&self) -> &[ChangeRecord<N>] {
+        &self.history
+    }
+
+    /// Returns the membership epoch: the number of times the group has changed. The epoch
+    /// depends only on the set of events in the graph and not on the order of their arrival.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns the actions in the graph that are not reflected in the group because they are
+    /// superseded by an unresolved conflicting action, in the topological order.
+    ///
+    /// Actions take effect as soon as their events are in the graph, in the topological order of
+    /// the events. An `Add` and a `Remove` of the same node conflict if their events are
+    /// concurrent, that is, neither is an ancestor of the other. The group then reflects the
+    /// action ordered later, and the earlier one is pending. The conflict is resolved, and the
+    /// earlier action is no longer pending, once some event has both conflicting events as
+    /// ancestors. Redundant actions without a conflict and the actions of pruned events are never
+    /// pending.
+    pub fn pending(&self) -> Vec<Action<N>> {
+        let order = self.graph.topological_order();
+        // The positions of the `Add` and of the `Remove` events of each node.
+        let mut candidates: BTreeMap<&N, (Vec<usize>, Vec<usize>)> = BTreeMap::new();
+        for (position, event) in order.iter().enumerate() {
+            match event.action() {
+                Action::Add(node) => candidates.entry(node).or_default().0.push(position),
+                Action::Remove(node) => candidates.entry(node).or_default().1.push(position),
+                Action::Init(_) => {}
+            }
+        }
+        // The indices of each candidate event and its descendants.
+        let descendants = |position: usize| -> BTreeSet<usize> {
+            let event = order[position].clone();
+            iter::once(event.index)
+                .chain(self.graph.descendants(event).map(|event| event.index))
+                .collect()
+        };
+        let mut pending = BTreeSet::new();
+        for (adds, removes) in candidates.values() {
+            if adds.is_empty() || removes.is_empty() {
+                continue;
+            }
+            let add_descendants: Vec<BTreeSet<usize>> =
+                adds.iter().map(|&add| descendants(add)).collect();
+            for &remove in removes {
+                let remove_descendants = descendants(remove);
+                for (&add, add_descendants) in adds.iter().zip(&add_descendants) {
+                    // Concurrent and without a common descendant.
+                    if add_descendants.is_disjoint(&remove_descendants) {
+                        pending.insert(add.min(remove));
+                    }
+                }
+            }
+        }
+        pending
+            .into_iter()
+            .map(|position| order[position].action().clone())
+            .collect()
+    }
+
+    /// Returns the group, the epoch, the change history and the departed nodes of the base
+    /// checkpoint, or the empty state without one.
+    #[allow(clippy::type_complexity)]
+    fn base_state(
+        &self,
+    ) -> (
+        BTreeSet<N>,
+        u64,
+        Vec<ChangeRecord<N>>,
+        BTreeMap<N, (u64, Hash)>,
+    ) {
+        match &self.base {
+            Some(base) => (
+                base.members.clone(),
+                base.epoch,
+                base.history.clone(),
+                base.departed.clone(),
+            ),
+            None => (BTreeSet::new(), 0, Vec::new(), BTreeMap::new()),
+        }
+    }
+
+    /// Replays the given actions onto a group and its epoch, and appends a record of every change
+    /// to `history`. Adding a present node and removing an absent node have no effect.
+    ///
+    /// A node that removes itself is recorded in `departed`, and its removal is final: later
+    /// actions, even concurrent ones, don't add it again.
+    fn replay(
+        members: &mut BTreeSet<N>,
+        epoch: &mut u64,
+        history: &mut Vec<ChangeRecord<N>>,
+        departed: &mut BTreeMap<N, (u64, Hash)>,
+        actions: Vec<ActionRef<'_, N>>,
+    ) {
+        for action_ref in actions {
+            if let Action::Remove(node) = action_ref.action {
+                if node == action_ref.creator_id {
+                    departed
+                        .entry(node.clone())
+                        .or_insert_with(|| (action_ref.lamport, action_ref.hash.clone()));
+                }
+            }
+            let changes: Vec<Action<N>> = match action_ref.action {
+                Action::Init(init) => init
+                    .iter()
+                    .filter(|node| !departed.contains_key(*node) && members.insert((*node).clone()))
+                    .map(|node| Action::Add(node.clone()))
+                    .collect(),
+                Action::Add(node)
+                    if !departed.contains_key(node) && members.insert(node.clone()) =>
+                {
+                    vec![Action::Add(node.clone())]
+                }
+                Action::Remove(node) if members.remove(node) => vec![Action::Remove(node.clone())],
+                _ => Vec::new(),
+            };
+            if changes.is_empty() {
+                continue;
+            }
+            *epoch += 1;
+            history.extend(changes.into_iter().map(|action| ChangeRecord {
+                action,
+                creator_id: action_ref.creator_id.clone(),
+                hash: action_ref.hash.clone(),
+                lamport: action_ref.lamport,
+                epoch: *epoch,
+            }));
+        }
+    }
+
+    /// Updates the group, the epoch and the change history after the insertion of the event with
+    /// the given index. The changes recorded from the position of the event in the topological
+    /// order on are undone, together with the departures from there on, and the actions from that
+    /// position on are replayed.
+    fn update_state(&mut self, index: usize) {
+        let (lamport, creator_id, hash) =
+            match (self.graph.get_by_index(index), self.graph.get_hash(index)) {
+                (Some(event), Some(hash)) => (event.lamport(), event.creator_id().clone(), hash),
+                _ => return,
+            };
+        let key = (lamport, &creator_id, hash);
+        let base_len = self.base.as_ref().map_or(0, |base| base.history.len());
+        let keep = self.history[base_len..]
+            .iter()
+            .position(|record| (record.lamport, &record.creator_id, &record.hash) >= key)
+            .map_or(self.history.len(), |position| base_len + position);
+        for record in self.history.drain(keep..).rev() {
+            match record.action {
+                Action::Add(node) => {
+                    self.members.remove(&node);
+                }
+                Action::Remove(node) => {
+                    self.members.insert(node);
+                }
+                Action::Init(_) => {}
+            }
+        }
+        let base_departed = self.base.as_ref().map(|base| &base.departed);
+        self.departed.retain(|node, (lamport, hash)| {
+            base_departed.is_some_and(|departed| departed.contains_key(node))
+                || (*lamport, node, &*hash) < key
+        });
+        self.epoch = match self.history[base_len..].last() {
+            Some(record) => record.epoch,
+            None => self.base.as_ref().map_or(0, |base| base.epoch),
+        };
+        Self::replay(
+            &mut self.members,
+            &mut self.epoch,
+            &mut self.history,
+            &mut self.departed,
+            self.graph.actions_from(lamport, &creator_id, hash),
+        );
+    }
+
+    /// Notifies the change observers of any membership change and passes outgoing messages to the
+    /// emit observers before they are returned.
+    fn emit(&mut self, messages: Vec<Message<N>>) -> Vec<Message<N>> {
+        self.emit_to(&Recipient::All, messages)
+    }
+
+    /// Notifies the change observers of any membership change and passes outgoing messages to the
+    /// emit observers with the given recipient before they are returned.
+    fn emit_to(&mut self, recipient: &Recipient<N>, messages: Vec<Message<N>>) -> Vec<Message<N>> {
+        self.notify_change();
+        for msg in &messages {
+            self.observe(recipient, msg);
+        }
+        messages
+    }
+
+    /// Passes an outgoing message to the emit observers.
+    fn observe(&mut self, recipient: &Recipient<N>, msg: &Message<N>) {
+        for observer in &mut self.emit_observers {
+            observer(recipient, msg);
+        }
+    }
+
+    /// Notifies the change observers if the group has changed since the previous notification.
+    fn notify_change(&mut self) {
+        if self.change_observers.is_empty() {
+            return;
+        }
+        let group = self.members.clone();
+        if group == self.notified_group {
+            return;
+        }
+        let change = MembershipChange {
+            added: group.difference(&self.notified_group).cloned().collect(),
+            removed: self.notified_group.difference(&group).cloned().collect(),
+        };
+        for observer in &mut self.change_observers {
+            observer(&change);
+        }
+        self.notified_group = group;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::rc::Rc;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::{
+        BatchError, ChangeRecord, Checkpoint, Error, MembershipChange, Message, NodeMembership,
+        Outbound, Recipient, ValidationError, DEFAULT_FANOUT,
+    };
+    use crate::failure_detector::{Error as FailureDetectorError, FailureDetector, NodeStatus};
+    use crate::graph::{Action, Event};
+    use crate::hash::{compute_hash, Hash};
+    use crate::signature::{Signer, Verifier};
+
+    /// Inserts an event into the graph without validation and updates the membership state.
+    fn insert(membership: &mut NodeMembership<u64>, event: Event<u64>) {
+        let index = membership.graph.insert(event).unwrap().index;
+        membership.update_state(index);
+    }
+
+    /// A failure detector that reports scripted failures.
+    struct MockFailureDetector {
+        /// The failures to report on the next poll.
+        failures: Vec<u64>,
+        /// The number of failures that have been dequeued.
+        dequeued: Rc<Cell<usize>>,
+    }
+
+    impl FailureDetector<u64> for MockFailureDetector {
+        fn poll_failures(&mut self) -> Result<(), FailureDetectorError> {
+            Ok(())
+        }
+
+        fn dequeue_failures(&mut self) -> Vec<u64> {
+            let failures: Vec<u64> = self.failures.drain(..).collect();
+            self.dequeued.set(self.dequeued.get() + failures.len());
+            failures
+        }
+    }
+
+    #[test]
+    fn poll_uses_injected_failure_detector() {
+        let dequeued = Rc::new(Cell::new(0));
+        let detector = MockFailureDetector {
+            failures: vec![1],
+            dequeued: dequeued.clone(),
+        };
+        let mut membership: NodeMembership<u64> =
+            NodeMembership::with_failure_detector(Box::new(detector));
+        assert!(membership.graph().creators().is_empty());
+        membership.poll().unwrap();
+        assert_eq!(dequeued.get(), 1);
+    }
+
+    #[test]
+    fn validate_event_reports_first_violation() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        let genesis = Event::new(0, None, None, 0, Action::Init(BTreeSet::new()));
+        let genesis_hash = genesis.hash().unwrap();
+        insert(&mut membership, genesis);
+        assert!(membership
+            .validate_event(&Event::new(
+                0,
+                Some(genesis_hash.clone()),
+                None,
+                1,
+                Action::Add(1)
+            ))
+            .is_ok());
+        // Both parents are the same missing event created by someone else.
+        let missing = compute_hash(&"missing").unwrap();
+        let event = Event::new(1, Some(missing.clone()), Some(missing), 1, Action::Add(2));
+        match membership.validate_event(&event) {
+            Err(ValidationError::DuplicateParent(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // The timestamp doesn't follow from the parents.
+        let event = Event::new(0, Some(genesis_hash.clone()), None, 5, Action::Add(1));
+        match membership.validate_event(&event) {
+            Err(ValidationError::InvalidLamport(5)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // The self-parent is present but has a different creator.
+        let event = Event::new(1, Some(genesis_hash), None, 1, Action::Add(2));
+        match membership.validate_event(&event) {
+            Err(ValidationError::SelfParentCreator(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // The size check precedes all the others.
+        membership.set_max_event_size(1);
+        match membership.validate_event(&event) {
+            Err(ValidationError::TooLarge(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn on_emit_observes_returned_messages() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        let observed = Rc::new(RefCell::new(Vec::new()));
+        let observed_clone = observed.clone();
+        membership.on_emit(Box::new(move |_, msg| {
+            observed_clone.borrow_mut().push(msg.clone())
+        }));
+        let genesis = Event::new(0, None, None, 0, Action::Init(BTreeSet::new()));
+        let output = membership
+            .handle_message(&Message::Event(genesis.clone()))
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(observed.borrow().len(), 1);
+        // Nothing is observed when nothing is returned.
+        let output = membership
+            .handle_message(&Message::Event(genesis.clone()))
+            .unwrap();
+        assert!(output.is_empty());
+        assert_eq!(observed.borrow().len(), 1);
+    }
+
+    #[test]
+    fn on_emit_observes_requests_and_replies() {
+        let mut requester: NodeMembership<u64> = NodeMembership::new();
+        let mut responder: NodeMembership<u64> = NodeMembership::new();
+        let observed = Rc::new(RefCell::new(Vec::new()));
+        for node in [&mut requester, &mut responder] {
+            let observed = observed.clone();
+            node.on_emit(Box::new(move |recipient, _| {
+                observed.borrow_mut().push(recipient.clone())
+            }));
+        }
+        let request = requester.sync_request(1);
+        responder.handle_message(&request).unwrap();
+        responder.handle_message(&Message::JoinRequest).unwrap();
+        assert_eq!(
+            *observed.borrow(),
+            vec![
+                Recipient::Node(1),
+                Recipient::Requester,
+                Recipient::Requester
+            ]
+        );
+    }
+
+    #[test]
+    fn on_emit_observes_polled_messages() {
+        let detector = MockFailureDetector {
+            failures: vec![1, 2],
+            dequeued: Rc::new(Cell::new(0)),
+        };
+        let mut membership: NodeMembership<u64> =
+            NodeMembership::with_failure_detector(Box::new(detector));
+        membership.set_our_id(0);
+        membership.set_fanout(2);
+        membership.init((0..5).collect()).unwrap();
+        let observed = Rc::new(RefCell::new(Vec::new()));
+        let observed_clone = observed.clone();
+        membership.on_emit(Box::new(move |recipient, msg| {
+            observed_clone
+                .borrow_mut()
+                .push((recipient.clone(), msg.clone()))
+        }));
+        let outbound = membership.poll().unwrap();
+        assert_eq!(outbound.len(), 2);
+        // Every returned message is observed exactly once, with its recipients.
+        let observed = observed.borrow();
+        assert_eq!(observed.len(), outbound.len());
+        for ((recipient, msg), out) in observed.iter().zip(&outbound) {
+            assert_eq!(recipient, &Recipient::Peers(out.recipients.clone()));
+            assert_eq!(
+                bincode::serialize(msg).unwrap(),
+                bincode::serialize(&out.message).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn events_undelivered_to_is_the_gap() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        let a0 = Event::new(0, None, None, 0, Action::Init(BTreeSet::new()));
+        let a0_hash = a0.hash().unwrap();
+        let a1 = Event::new(0, Some(a0_hash), None, 1, Action::Add(1));
+        let a1_hash = a1.hash().unwrap();
+        insert(&mut membership, a0);
+        insert(&mut membership, a1);
+        assert_eq!(membership.events_undelivered_to(&1).len(), 2);
+        membership.record_peer_summary(1, vec![(0, 1)].into_iter().collect());
+        // An older summary doesn't make the peer lose events.
+        membership.record_peer_summary(1, BTreeMap::new());
+        let undelivered: Vec<_> = membership
+            .events_undelivered_to(&1)
+            .into_iter()
+            .map(|event| event.event.hash().unwrap())
+            .collect();
+        assert_eq!(undelivered, vec![a1_hash]);
+    }
+
+    #[test]
+    fn group_replays_actions() {
+        let init = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        let init_hash = init.hash().unwrap();
+        let add2 = Event::new(0, Some(init_hash.clone()), None, 1, Action::Add(2));
+        let add2_hash = add2.hash().unwrap();
+        let add2_again = Event::new(1, None, Some(add2_hash.clone()), 2, Action::Add(2));
+        let add2_again_hash = add2_again.hash().unwrap();
+        let remove1 = Event::new(
+            0,
+            Some(add2_hash),
+            Some(add2_again_hash),
+            3,
+            Action::Remove(1),
+        );
+        let remove1_hash = remove1.hash().unwrap();
+        let remove9 = Event::new(0, Some(remove1_hash), None, 4, Action::Remove(9));
+        let events = vec![init, add2, add2_again, remove1, remove9];
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        for event in events.iter().cloned() {
+            insert(&mut membership, event);
+        }
+        assert_eq!(membership.group(), vec![0, 2]);
+        // The group doesn't depend on the order of insertion into the graph.
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        for event in events.into_iter().rev() {
+            insert(&mut membership, event);
+        }
+        assert_eq!(membership.group(), vec![0, 2]);
+    }
+
+    #[test]
+    fn handle_message_forwards_new_events_once() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        let genesis = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0].into_iter().collect()),
+        );
+        let hash = genesis.hash().unwrap();
+        let msg = Message::Event(genesis);
+        assert_eq!(membership.handle_message(&msg).unwrap().len(), 1);
+        assert!(membership.graph().contains(&hash));
+        assert!(membership.handle_message(&msg).unwrap().is_empty());
+        assert_eq!(membership.group(), vec![0]);
+    }
+
+    #[test]
+    fn inserted_events_report_creator_activity() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        membership.set_our_id(0);
+        let genesis = Event::new(
+            1,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        assert_eq!(membership.failure_detector.status(&1), None);
+        membership.handle_message(&Message::Event(genesis)).unwrap();
+        assert_eq!(
+            membership.failure_detector.status(&1),
+            Some(NodeStatus::Alive)
+        );
+        membership.propose_add(2).unwrap();
+        assert_eq!(membership.failure_detector.status(&0), None);
+    }
+
+    #[test]
+    fn piggybacked_summary_updates_peer_frontier() {
+        let mut sender: NodeMembership<u64> = NodeMembership::new();
+        let a0 = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        let a0_hash = a0.hash().unwrap();
+        insert(&mut sender, a0.clone());
+        let a1 = Event::new(0, Some(a0_hash), None, 1, Action::Add(2));
+        let a1_hash = a1.hash().unwrap();
+        let msg = sender.summarize_event(1, a1).unwrap();
+        let mut receiver: NodeMembership<u64> = NodeMembership::new();
+        receiver.handle_message(&Message::Event(a0)).unwrap();
+        assert_eq!(receiver.handle_message(&msg).unwrap().len(), 1);
+        // The sender holds `a0` according to the summary.
+        assert_eq!(receiver.peer_summaries[&1][&0], 1);
+        let undelivered: Vec<_> = receiver
+            .events_undelivered_to(&1)
+            .into_iter()
+            .map(|event| event.event.hash().unwrap())
+            .collect();
+        assert_eq!(undelivered, vec![a1_hash]);
+    }
+
+    #[test]
+    fn summaries_of_verified_senders_are_authenticated() {
+        let mut sender: NodeMembership<u64> = NodeMembership::new();
+        sender.set_signer(Box::new(MockKey(1)));
+        let a0 = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        insert(&mut sender, a0.clone());
+        let mut receiver: NodeMembership<u64> = NodeMembership::new();
+        receiver.set_verifier(1, Box::new(MockKey(1)));
+        // A summary claiming more events than the sender signed.
+        let forged = match sender.summarize_event(1, a0.clone()).unwrap() {
+            Message::SummarizedEvent {
+                sender,
+                event,
+                signature,
+                ..
+            } => Message::SummarizedEvent {
+                sender,
+                event,
+                summary: vec![(0, 9)].into_iter().collect(),
+                signature,
+            },
+            msg => panic!("unexpected message {:?}", msg),
+        };
+        let unsigned = NodeMembership::<u64>::new()
+            .summarize_event(1, a0.clone())
+            .unwrap();
+        for msg in [forged, unsigned] {
+            match receiver.handle_message(&msg) {
+                Err(Error::BadSignature { creator }) => assert_eq!(creator, 1),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        assert!(!receiver.peer_summaries.contains_key(&1));
+        // A summary on an event that is rejected is not recorded.
+        let orphan = Event::new(0, Some(Hash([0; 32])), None, 1, Action::Add(2));
+        let msg = sender.summarize_event(1, orphan).unwrap();
+        receiver.handle_message(&msg).unwrap_err();
+        assert!(!receiver.peer_summaries.contains_key(&1));
+        let msg = sender.summarize_event(1, a0).unwrap();
+        assert_eq!(receiver.handle_message(&msg).unwrap().len(), 1);
+        assert_eq!(receiver.peer_summaries[&1][&0], 1);
+    }
+
+    #[test]
+    fn validate_batch_rejects_missing_dependency() {
+        let membership: NodeMembership<u64> = NodeMembership::new();
+        let a0 = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        let a0_hash = a0.hash().unwrap();
+        let a1 = Event::new(0, Some(a0_hash.clone()), None, 1, Action::Add(2));
+        let a1_hash = a1.hash().unwrap();
+        let b0 = Event::new(1, None, Some(a1_hash), 2, Action::Add(3));
+        membership
+            .validate_batch(&[a0.clone(), a1, b0.clone()])
+            .unwrap();
+        match membership.validate_batch(&[a0.clone(), b0]) {
+            Err(BatchError {
+                position: 1,
+                error: Error::Validation(ValidationError::MissingParent(_)),
+            }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let c0 = Event::new(2, None, Some(a0_hash.clone()), 1, Action::Add(3));
+        match membership.validate_batch(&[a0.clone(), c0]) {
+            Err(BatchError {
+                position: 1,
+                error: Error::NotMember { creator: 2 },
+            }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let mut signed: NodeMembership<u64> = NodeMembership::new();
+        signed.set_verifier(0, Box::new(MockKey(0)));
+        match signed.validate_batch(&[a0]) {
+            Err(BatchError {
+                position: 0,
+                error: Error::BadSignature { creator: 0 },
+            }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // The state isn't mutated.
+        assert!(!membership.graph().contains(&a0_hash));
+    }
+
+    /// Runs a sync of the requester with the responder and checks the number of events synced.
+    fn sync(
+        requester: &mut NodeMembership<u64>,
+        responder: &mut NodeMembership<u64>,
+        count: usize,
+    ) {
+        let request = requester.sync_request(1);
+        let mut response = responder.handle_message(&request).unwrap();
+        assert_eq!(response.len(), 1);
+        match &response[0] {
+            Message::SyncResponse { events } => assert_eq!(events.len(), count),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        assert!(requester
+            .handle_message(&response.remove(0))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn sync_converges_two_nodes() {
+        let a0 = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        let a0_hash = a0.hash().unwrap();
+        let a1 = Event::new(0, Some(a0_hash.clone()), None, 1, Action::Add(2));
+        let b0 = Event::new(1, None, Some(a0_hash), 1, Action::Add(3));
+        let b0_hash = b0.hash().unwrap();
+        let b1 = Event::new(1, Some(b0_hash), None, 2, Action::Remove(3));
+        let mut node0: NodeMembership<u64> = NodeMembership::new();
+        let mut node1: NodeMembership<u64> = NodeMembership::new();
+        for event in [a0.clone(), a1] {
+            insert(&mut node0, event);
+        }
+        for event in [a0, b0, b1] {
+            insert(&mut node1, event);
+        }
+        // The tip of node 0 is unknown to node 1, so the genesis event is sent as well.
+        sync(&mut node0, &mut node1, 3);
+        sync(&mut node1, &mut node0, 1);
+        assert_eq!(
+            node0.graph().to_bytes().unwrap(),
+            node1.graph().to_bytes().unwrap()
+        );
+        assert_eq!(node0.group(), vec![0, 1, 2]);
+        // A converged peer is missing nothing.
+        match &node1.handle_message(&node0.sync_request(1)).unwrap()[0] {
+            Message::SyncResponse { events } => assert!(events.is_empty()),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn proposals_change_group() {
+        let mut node0: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        let mut node1: NodeMembership<u64> = NodeMembership::with_our_id(1);
+        let genesis = Event::new(
+            2,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        insert(&mut node0, genesis.clone());
+        insert(&mut node1, genesis);
+        assert!(node0.propose_add(1).unwrap().is_empty());
+        let msgs = node0.propose_add(3).unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(node0.group(), vec![0, 1, 3]);
+        match &msgs[0] {
+            Message::Event(event) => {
+                assert_eq!(*event.creator_id(), 0);
+                assert!(event.self_parent().is_none());
+                assert!(event.other_parent().is_some());
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        // The proposal is gossiped to node 1.
+        node1.handle_message(&msgs[0]).unwrap();
+        assert_eq!(node1.group(), vec![0, 1, 3]);
+        let msgs = node1.propose_remove(0).unwrap();
+        assert!(node1.propose_remove(0).unwrap().is_empty());
+        node0.handle_message(&msgs[0]).unwrap();
+        assert_eq!(node0.group(), vec![1, 3]);
+    }
+
+    #[test]
+    fn proposals_require_local_id() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        match membership.propose_add(1) {
+            Err(Error::NoLocalId) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn init_registers_initial_group_once() {
+        let mut membership: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        let members: BTreeSet<u64> = vec![0, 1, 2].into_iter().collect();
+        let msgs = membership.init(members.clone()).unwrap();
+        match msgs.as_slice() {
+            [Message::Event(event)] => {
+                assert_eq!(*event.action(), Action::Init(members.clone()));
+                assert!(event.self_parent().is_none() && event.other_parent().is_none());
+            }
+            msgs => panic!("unexpected messages {:?}", msgs),
+        }
+        assert_eq!(membership.group(), vec![0, 1, 2]);
+        match membership.init(members) {
+            Err(Error::AlreadyInitialized) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(membership.graph().summary().get(&0), Some(&1));
+    }
+
+    #[test]
+    fn poll_removes_failed_members() {
+        let detector = MockFailureDetector {
+            failures: vec![1, 1],
+            dequeued: Rc::new(Cell::new(0)),
+        };
+        let mut membership: NodeMembership<u64> =
+            NodeMembership::with_failure_detector(Box::new(detector));
+        membership.set_our_id(0);
+        membership.init(vec![0, 1].into_iter().collect()).unwrap();
+        let msgs = membership.poll().unwrap();
+        match msgs.as_slice() {
+            [Outbound {
+                recipients,
+                message: Message::Event(event),
+            }] => {
+                assert_eq!(*event.action(), Action::Remove(1));
+                assert!(recipients.is_empty());
+            }
+            msgs => panic!("unexpected messages {:?}", msgs),
+        }
+        assert_eq!(membership.group(), vec![0]);
+    }
+
+    #[test]
+    fn on_change_observes_group_delta() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let changes_clone = changes.clone();
+        membership.on_change(Box::new(move |change| {
+            changes_clone.borrow_mut().push(change.clone())
+        }));
+        let genesis = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0].into_iter().collect()),
+        );
+        let genesis_hash = genesis.hash().unwrap();
+        membership
+            .handle_message(&Message::Event(genesis.clone()))
+            .unwrap();
+        let add = Event::new(0, Some(genesis_hash), None, 1, Action::Add(1));
+        membership.handle_message(&Message::Event(add)).unwrap();
+        // A known event doesn't change the group.
+        membership.handle_message(&Message::Event(genesis)).unwrap();
+        let change = |added: Vec<u64>| MembershipChange {
+            added: added.into_iter().collect(),
+            removed: BTreeSet::new(),
+        };
+        assert_eq!(*changes.borrow(), vec![change(vec![0]), change(vec![1])]);
+    }
+
+    #[test]
+    fn epoch_is_independent_of_arrival_order() {
+        let a0 = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0].into_iter().collect()),
+        );
+        let a0_hash = a0.hash().unwrap();
+        let a1 = Event::new(0, Some(a0_hash.clone()), None, 1, Action::Add(1));
+        let b0 = Event::new(1, None, Some(a0_hash.clone()), 1, Action::Add(1));
+        let c0 = Event::new(2, None, Some(a0_hash), 1, Action::Remove(0));
+        let events = [a0, a1, b0, c0];
+        let mut node0: NodeMembership<u64> = NodeMembership::new();
+        let mut node1: NodeMembership<u64> = NodeMembership::new();
+        for event in events.iter() {
+            insert(&mut node0, event.clone());
+        }
+        for event in events.iter().rev() {
+            insert(&mut node1, event.clone());
+        }
+        // The second addition of node 1 has no effect.
+        assert_eq!(node0.epoch(), 3);
+        assert_eq!(node1.epoch(), node0.epoch());
+        assert_eq!(node1.group(), node0.group());
+        assert_eq!(node1.history(), node0.history());
+    }
+
+    #[test]
+    fn message_is_send_and_sync() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<Message<u64>>();
+        assert_sync::<Message<u64>>();
+    }
+
+    #[test]
+    fn membership_of_creators_is_checked_in_the_causal_past() {
+        let a0 = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        let a0_hash = a0.hash().unwrap();
+        let remove = Event::new(1, None, Some(a0_hash.clone()), 1, Action::Remove(0));
+        let remove_hash = remove.hash().unwrap();
+        // Concurrent with the removal of its creator.
+        let a1 = Event::new(0, Some(a0_hash), None, 1, Action::Add(2));
+        let a1_hash = a1.hash().unwrap();
+        // A successor of the removal of its creator.
+        let a2 = Event::new(0, Some(a1_hash), Some(remove_hash), 2, Action::Add(3));
+        let mut node1: NodeMembership<u64> = NodeMembership::new();
+        let mut node2: NodeMembership<u64> = NodeMembership::new();
+        for event in [&a0, &remove, &a1] {
+            node1
+                .handle_message(&Message::Event(event.clone()))
+                .unwrap();
+        }
+        for event in [&a0, &a1, &remove] {
+            node2
+                .handle_message(&Message::Event(event.clone()))
+                .unwrap();
+        }
+        assert_eq!(
+            node1.graph().to_bytes().unwrap(),
+            node2.graph().to_bytes().unwrap()
+        );
+        assert_eq!(node1.group(), node2.group());
+        for node in [&mut node1, &mut node2] {
+            match node.handle_message(&Message::Event(a2.clone())) {
+                Err(Error::NotMember { creator }) => assert_eq!(creator, 0),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn non_members_cannot_propose() {
+        let mut membership: NodeMembership<u64> = NodeMembership::with_our_id(1);
+        let genesis = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0].into_iter().collect()),
+        );
+        membership.handle_message(&Message::Event(genesis)).unwrap();
+        match membership.propose_add(2) {
+            Err(Error::NotMember { creator }) => assert_eq!(creator, 1),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(membership.graph().creators().len(), 1);
+    }
+
+    #[test]
+    fn init_events_after_genesis_are_rejected() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        let genesis = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        let genesis_hash = genesis.hash().unwrap();
+        membership.handle_message(&Message::Event(genesis)).unwrap();
+        let add = Event::new(0, Some(genesis_hash.clone()), None, 1, Action::Add(2));
+        let add_hash = add.hash().unwrap();
+        membership.handle_message(&Message::Event(add)).unwrap();
+        let outsider = Event::new(
+            9,
+            None,
+            Some(add_hash),
+            2,
+            Action::Init(vec![9].into_iter().collect()),
+        );
+        let second_genesis = Event::new(
+            9,
+            None,
+            None,
+            0,
+            Action::Init(vec![9].into_iter().collect()),
+        );
+        for event in [outsider, second_genesis] {
+            match membership.handle_message(&Message::Event(event)) {
+                Err(Error::AlreadyInitialized) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        assert_eq!(membership.group(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn events_by_non_members_are_rejected() {
+        let mut membership: NodeMembership<u64> = NodeMembership::new();
+        let genesis = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0].into_iter().collect()),
+        );
+        let genesis_hash = genesis.hash().unwrap();
+        membership.handle_message(&Message::Event(genesis)).unwrap();
+        let add = Event::new(0, Some(genesis_hash.clone()), None, 1, Action::Add(1));
+        membership.handle_message(&Message::Event(add)).unwrap();
+        let intruder = Event::new(2, None, Some(genesis_hash), 1, Action::Add(3));
+        match membership.handle_message(&Message::Event(intruder)) {
+            Err(Error::NotMember { creator }) => assert_eq!(creator, 2),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(membership.group(), vec![0, 1]);
+    }
+
+    /// A keyed signature scheme standing in for ed25519: the signature is the hash of the key
+    /// and the message.
+    struct MockKey(u64);
+
+    impl MockKey {
+        fn signature(&self, bytes: &[u8]) -> Vec<u8> {
+            compute_hash(&(self.0, bytes)).unwrap().0.to_vec()
+        }
+    }
+
+    impl Signer for MockKey {
+        fn sign(&self, bytes: &[u8]) -> Vec<u8> {
+            self.signature(bytes)
+        }
+    }
+
+    impl Verifier for MockKey {
+        fn verify(&self, bytes: &[u8], signature: &[u8]) -> bool {
+            self.signature(bytes) == signature
+        }
+    }
+
+    #[test]
+    fn signatures_are_verified() {
+        let mut node0: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        node0.set_signer(Box::new(MockKey(7)));
+        let mut node1: NodeMembership<u64> = NodeMembership::with_our_id(1);
+        node1.set_verifier(0, Box::new(MockKey(7)));
+        let init = node0.init(vec![0, 1].into_iter().collect()).unwrap();
+        node1.handle_message(&init[0]).unwrap();
+        let add = match node0.propose_add(2).unwrap().remove(0) {
+            Message::Event(event) => event,
+            msg => panic!("unexpected message {:?}", msg),
+        };
+        assert!(add.signature().is_some());
+        // The action is tampered with while the signature is kept.
+        let tampered = Event::new(
+            0,
+            add.self_parent().cloned(),
+            add.other_parent().cloned(),
+            add.lamport(),
+            Action::Add(3),
+        )
+        .with_signature(add.signature().unwrap().to_vec());
+        match node1.handle_message(&Message::Event(tampered)) {
+            Err(Error::BadSignature { creator }) => assert_eq!(creator, 0),
+            r => panic!("unexpected result {:?}", r),
+        }
+        node1.handle_message(&Message::Event(add)).unwrap();
+        assert_eq!(node1.group(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn leave_removes_local_node_everywhere() {
+        let detector = MockFailureDetector {
+            failures: vec![1],
+            dequeued: Rc::new(Cell::new(0)),
+        };
+        let mut node0: NodeMembership<u64> =
+            NodeMembership::with_failure_detector(Box::new(detector));
+        node0.set_our_id(0);
+        let mut peers: Vec<NodeMembership<u64>> = (1..3).map(NodeMembership::with_our_id).collect();
+        let mut msgs = node0.init(vec![0, 1, 2].into_iter().collect()).unwrap();
+        msgs.extend(node0.leave().unwrap());
+        for peer in peers.iter_mut() {
+            for msg in msgs.iter() {
+                peer.handle_message(msg).unwrap();
+            }
+            assert_eq!(peer.group(), vec![1, 2]);
+        }
+        assert_eq!(node0.group(), vec![1, 2]);
+        match node0.leave() {
+            Err(Error::HasLeft) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match node0.propose_add(0) {
+            Err(Error::HasLeft) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match node0.propose_remove(1) {
+            Err(Error::HasLeft) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // The detected failure of node 1 no longer leads to a proposal.
+        assert!(node0.poll().unwrap().is_empty());
+    }
+
+    #[test]
+    fn self_removal_is_final() {
+        let a0 = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        let a0_hash = a0.hash().unwrap();
+        let a1 = Event::new(0, Some(a0_hash.clone()), None, 1, Action::Remove(0));
+        // Concurrent with the removal and ordered after it.
+        let b0 = Event::new(1, None, Some(a0_hash), 1, Action::Add(0));
+        let events = [a0, a1, b0];
+        let mut node1: NodeMembership<u64> = NodeMembership::new();
+        let mut node2: NodeMembership<u64> = NodeMembership::new();
+        for event in events.iter() {
+            insert(&mut node1, event.clone());
+        }
+        for event in events.iter().rev() {
+            insert(&mut node2, event.clone());
+        }
+        assert_eq!(node1.group(), vec![1]);
+        assert_eq!(node2.group(), vec![1]);
+        assert_eq!(node1.checkpoint(), node2.checkpoint());
+    }
+
+    #[test]
+    fn pruned_events_are_not_taken_as_delivered() {
+        let mut node0: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        let mut node1: NodeMembership<u64> = NodeMembership::with_our_id(1);
+        let mut msgs = node0.init(vec![0, 1].into_iter().collect()).unwrap();
+        msgs.extend(node0.propose_add(2).unwrap());
+        for msg in &msgs {
+            node1.handle_message(msg).unwrap();
+        }
+        node0.record_peer_summary(1, node1.graph().summary());
+        let keep_after = match node0.propose_add(3).unwrap().remove(0) {
+            Message::Event(event) => event.hash().unwrap(),
+            msg => panic!("unexpected message {:?}", msg),
+        };
+        node0.propose_add(4).unwrap();
+        let summary = node0.graph().summary();
+        assert_eq!(node0.prune(&keep_after).unwrap(), 2);
+        assert_eq!(node0.graph().summary(), summary);
+        // The peer still gets the events it lacks.
+        let undelivered: Vec<Event<u64>> = node0
+            .events_undelivered_to(&1)
+            .into_iter()
+            .map(|event| event.event.clone())
+            .collect();
+        assert_eq!(undelivered.len(), 2);
+        for event in undelivered {
+            node1.handle_message(&Message::Event(event)).unwrap();
+        }
+        assert_eq!(node1.group(), node0.group());
+        assert_eq!(node1.graph().summary(), summary);
+        // A restored node continues the sequence numbers of the checkpoint.
+        let restored: NodeMembership<u64> = NodeMembership::restore(node0.checkpoint());
+        assert_eq!(restored.graph().summary(), summary);
+    }
+
+    #[test]
+    fn prune_preserves_group() {
+        let mut membership: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        membership.init(vec![0, 1].into_iter().collect()).unwrap();
+        membership.propose_add(2).unwrap();
+        membership.propose_remove(1).unwrap();
+        let keep_after = match membership.propose_add(3).unwrap().remove(0) {
+            Message::Event(event) => event.hash().unwrap(),
+            msg => panic!("unexpected message {:?}", msg),
+        };
+        let (group, epoch) = (membership.group(), membership.epoch());
+        let history = membership.history().to_vec();
+        assert_eq!(membership.prune(&keep_after).unwrap(), 3);
+        assert_eq!(membership.group(), group);
+        assert_eq!(membership.epoch(), epoch);
+        assert_eq!(membership.history(), &history[..]);
+        // The local node continues its chain from the retained tip.
+        membership.propose_remove(2).unwrap();
+        assert_eq!(membership.group(), vec![0, 3]);
+    }
+
+    #[test]
+    fn restore_reproduces_group() {
+        let mut node0: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        node0.init(vec![0, 1].into_iter().collect()).unwrap();
+        node0.propose_add(2).unwrap();
+        node0.propose_remove(1).unwrap();
+        let checkpoint = node0.checkpoint();
+        let bytes = bincode::serialize(&checkpoint).unwrap();
+        let checkpoint: Checkpoint<u64> = bincode::deserialize(&bytes).unwrap();
+        let mut node1: NodeMembership<u64> = NodeMembership::restore(checkpoint.clone());
+        assert_eq!(node1.group(), vec![0, 2]);
+        assert_eq!(node1.epoch(), node0.epoch());
+        assert!(node1.graph().creators().is_empty());
+        assert_eq!(node1.checkpoint(), checkpoint);
+        // Events following the checkpoint are accepted.
+        let msgs = node0.propose_add(3).unwrap();
+        node1.handle_message(&msgs[0]).unwrap();
+        assert_eq!(node1.group(), node0.group());
+    }
+
+    #[test]
+    fn joining_node_converges() {
+        let mut nodes: Vec<NodeMembership<u64>> = (0..3).map(NodeMembership::with_our_id).collect();
+        fn broadcast(nodes: &mut [NodeMembership<u64>], from: usize, msgs: Vec<Message<u64>>) {
+            for msg in msgs {
+                for (i, node) in nodes.iter_mut().enumerate() {
+                    if i != from {
+                        node.handle_message(&msg).unwrap();
+                    }
+                }
+            }
+        }
+        let msgs = nodes[0].init(vec![0, 1, 2].into_iter().collect()).unwrap();
+        broadcast(&mut nodes, 0, msgs);
+        for (from, node) in [(1, 5), (2, 6), (0, 7)] {
+            let msgs = nodes[from].propose_add(node).unwrap();
+            broadcast(&mut nodes, from, msgs);
+        }
+        let msgs = nodes[1].propose_remove(6).unwrap();
+        broadcast(&mut nodes, 1, msgs);
+        // The seed only sends its two most recent events along with a checkpoint.
+        nodes[1].set_max_join_events(2);
+        let mut joiner: NodeMembership<u64> = NodeMembership::with_our_id(3);
+        let request = joiner.request_join(1);
+        let response = nodes[1].handle_message(&request).unwrap();
+        match &response[..] {
+            [Message::JoinResponse { events, .. }] => assert_eq!(events.len(), 2),
+            msgs => panic!("unexpected messages {:?}", msgs),
+        }
+        assert!(joiner.handle_message(&response[0]).unwrap().is_empty());
+        assert_eq!(joiner.group(), vec![0, 1, 2, 5, 7]);
+        assert_eq!(joiner.epoch(), nodes[1].epoch());
+        // The joiner is not a member until it is added by the group.
+        match joiner.handle_message(&response[0]) {
+            Err(Error::AlreadyInitialized) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let msgs = nodes[2].propose_add(3).unwrap();
+        for msg in &msgs {
+            joiner.handle_message(msg).unwrap();
+        }
+        broadcast(&mut nodes, 2, msgs);
+        // Once added, the events of the joiner are accepted by the group.
+        let msgs = joiner.propose_remove(7).unwrap();
+        broadcast(&mut nodes, 3, msgs);
+        for node in &nodes {
+            assert_eq!(node.group(), vec![0, 1, 2, 3, 5]);
+            assert_eq!(node.epoch(), joiner.epoch());
+        }
+        assert_eq!(joiner.group(), vec![0, 1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn concurrent_conflict_is_pending_until_resolved() {
+        let a0 = Event::new(
+            0,
+            None,
+            None,
+            0,
+            Action::Init(vec![0, 1].into_iter().collect()),
+        );
+        let a0_hash = a0.hash().unwrap();
+        // Concurrent proposals to add and to remove node 5.
+        let a1 = Event::new(0, Some(a0_hash.clone()), None, 1, Action::Add(5));
+        let a1_hash = a1.hash().unwrap();
+        let b0 = Event::new(1, None, Some(a0_hash), 1, Action::Remove(5));
+        let b0_hash = b0.hash().unwrap();
+        let mut node: NodeMembership<u64> = NodeMembership::new();
+        for event in [a0, a1, b0] {
+            node.handle_message(&Message::Event(event)).unwrap();
+        }
+        // The removal is ordered after the addition and supersedes it.
+        assert_eq!(node.group(), vec![0, 1]);
+        assert_eq!(node.pending(), vec![Action::Add(5)]);
+        // An event that has seen both proposals resolves the conflict.
+        let a2 = Event::new(0, Some(a1_hash), Some(b0_hash), 2, Action::Add(6));
+        node.handle_message(&Message::Event(a2)).unwrap();
+        assert!(node.pending().is_empty());
+        assert_eq!(node.group(), vec![0, 1, 6]);
+    }
+
+    #[test]
+    fn history_records_changes_in_order() {
+        let mut node0: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        let mut hashes = Vec::new();
+        let mut record = |msgs: Vec<Message<u64>>| match &msgs[..] {
+            [Message::Event(event)] => hashes.push(event.hash().unwrap()),
+            msgs => panic!("unexpected messages {:?}", msgs),
+        };
+        record(node0.init(vec![0, 1].into_iter().collect()).unwrap());
+        record(node0.propose_add(2).unwrap());
+        let checkpoint = node0.checkpoint();
+        record(node0.propose_remove(1).unwrap());
+        record(node0.propose_add(1).unwrap());
+        let change = |action, event: usize, epoch| ChangeRecord {
+            action,
+            creator_id: 0,
+            hash: hashes[event].clone(),
+            lamport: event as u64,
+            epoch,
+        };
+        let expected = [
+            change(Action::Add(0), 0, 1),
+            change(Action::Add(1), 0, 1),
+            change(Action::Add(2), 1, 2),
+            change(Action::Remove(1), 2, 3),
+            change(Action::Add(1), 3, 4),
+        ];
+        assert_eq!(node0.history(), &expected[..]);
+        // The history survives a restore from a checkpoint followed by the later events.
+        let mut node1: NodeMembership<u64> = NodeMembership::restore(checkpoint);
+        assert_eq!(node1.history(), &expected[..3]);
+        for event in node0.graph().topological_order().into_iter().skip(2) {
+            node1
+                .handle_message(&Message::Event(event.event.clone()))
+                .unwrap();
+        }
+        assert_eq!(node1.history(), &expected[..]);
+    }
+
+    #[test]
+    fn poll_gossips_to_random_peers() {
+        let detector = MockFailureDetector {
+            failures: vec![5, 6],
+            dequeued: Rc::new(Cell::new(0)),
+        };
+        let mut membership: NodeMembership<u64> =
+            NodeMembership::with_failure_detector(Box::new(detector));
+        membership.set_our_id(0);
+        membership.set_rng(Box::new(StdRng::seed_from_u64(0)));
+        membership.init((0..7).collect()).unwrap();
+        let msgs = membership.poll().unwrap();
+        assert_eq!(msgs.len(), 2);
+        for msg in &msgs {
+            let recipients: BTreeSet<u64> = msg.recipients.iter().cloned().collect();
+            assert_eq!(recipients.len(), DEFAULT_FANOUT);
+            assert!(recipients.iter().all(|node| (1..5).contains(node)));
+        }
+        // A group smaller than the fanout gets every message in full.
+        membership.set_fanout(10);
+        membership.failure_detector = Box::new(MockFailureDetector {
+            failures: vec![4],
+            dequeued: Rc::new(Cell::new(0)),
+        });
+        match &membership.poll().unwrap()[..] {
+            [msg] => assert_eq!(msg.recipients, vec![1, 2, 3]),
+            msgs => panic!("unexpected messages {:?}", msgs),
+        }
+    }
+
+    #[test]
+    fn reflected_event_quiesces() {
+        let mut node0: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        let mut node1: NodeMembership<u64> = NodeMembership::with_our_id(1);
+        let mut msgs = node0.init(vec![0, 1].into_iter().collect()).unwrap();
+        let mut hops = 0;
+        while !msgs.is_empty() {
+            let receiver = if hops % 2 == 0 {
+                &mut node1
+            } else {
+                &mut node0
+            };
+            msgs = msgs
+                .iter()
+                .flat_map(|msg| receiver.handle_message(msg).unwrap())
+                .collect();
+            hops += 1;
+        }
+        // Node 1 forwards the event once, and node 0 already knows it.
+        assert_eq!(hops, 2);
+        assert_eq!(node1.group(), vec![0, 1]);
+    }
+
+    #[test]
+    fn is_member_and_len_follow_group() {
+        let mut node: NodeMembership<u64> = NodeMembership::with_our_id(0);
+        assert!(node.is_empty());
+        node.init(vec![0, 1].into_iter().collect()).unwrap();
+        assert!(node.is_member(&1));
+        assert!(!node.is_member(&2));
+        node.propose_add(2).unwrap();
+        node.propose_add(3).unwrap();
+        node.propose_remove(1).unwrap();
+        assert!(!node.is_member(&1));
+        assert!(node.is_member(&3));
+        assert_eq!(node.len(), 3);
+        assert_eq!(node.len(), node.group().len());
     }
 }
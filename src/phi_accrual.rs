@@ -0,0 +1,187 @@
+//! The phi-accrual failure detector of Hayashibara et al.
+//!
+//! Instead of a binary verdict, the detector computes a suspicion level phi for each node from the
+//! distribution of the intervals between the heartbeats received from that node. Phi grows with
+//! the time since the last heartbeat, the faster the more regular the heartbeats have been. A node
+//! is declared failed when its phi exceeds the configured threshold.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::failure_detector::{Clock, Error, FailureDetector, SystemClock};
+use crate::graph::NodeId;
+
+/// The default phi threshold above which a node is declared failed.
+pub const DEFAULT_THRESHOLD: f64 = 8.0;
+
+/// The default number of heartbeat intervals kept per node.
+pub const DEFAULT_WINDOW_SIZE: usize = 100;
+
+/// The default lower bound for the standard deviation of heartbeat intervals. It prevents
+/// perfectly regular heartbeats from making the detector oversensitive.
+pub const DEFAULT_MIN_STD_DEVIATION: Duration = Duration::from_millis(100);
+
+/// The heartbeat history of a node.
+struct History {
+    /// The arrival time of the last heartbeat.
+    last: Instant,
+    /// The most recent intervals between heartbeats in seconds.
+    intervals: VecDeque<f64>,
+}
+
+/// A phi-accrual failure detector.
+pub struct PhiAccrualDetector<N: NodeId, C: Clock = SystemClock> {
+    /// The phi threshold above which a node is declared failed.
+    threshold: f64,
+    /// The maximum number of heartbeat intervals kept per node.
+    window_size: usize,
+    /// The lower bound for the standard deviation of heartbeat intervals in seconds.
+    min_std_deviation: f64,
+    /// The heartbeat histories of the tracked nodes.
+    histories: BTreeMap<N, History>,
+    /// The queue of unhandled node failures.
+    failures: Vec<N>,
+    /// The source of the current time.
+    clock: C,
+}
+
+impl<N: NodeId> Default for PhiAccrualDetector<N> {
+    fn default() -> Self {
+        PhiAccrualDetector::new(DEFAULT_THRESHOLD, DEFAULT_WINDOW_SIZE, SystemClock)
+    }
+}
+
+impl<N: NodeId, C: Clock> PhiAccrualDetector<N, C> {
+    /// Creates a new phi-accrual failure detector.
+    pub fn new(threshold: f64, window_size: usize, clock: C) -> Self {
+        PhiAccrualDetector {
+            threshold,
+            window_size,
+            min_std_deviation: DEFAULT_MIN_STD_DEVIATION.as_secs_f64(),
+            histories: BTreeMap::new(),
+            failures: Vec::new(),
+            clock,
+        }
+    }
+
+    /// Sets the lower bound for the standard deviation of heartbeat intervals.
+    pub fn set_min_std_deviation(&mut self, min_std_deviation: Duration) {
+        self.min_std_deviation = min_std_deviation.as_secs_f64();
+    }
+
+    /// Records the arrival of a heartbeat from a node. The node is tracked from its first
+    /// heartbeat on.
+    pub fn heartbeat(&mut self, node: N, now: Instant) {
+        let window_size = self.window_size;
+        let history = self.histories.entry(node).or_insert_with(|| History {
+            last: now,
+            intervals: VecDeque::new(),
+        });
+        if now > history.last {
+            if history.intervals.len() == window_size {
+                history.intervals.pop_front();
+            }
+            history
+                .intervals
+                .push_back(now.duration_since(history.last).as_secs_f64());
+            history.last = now;
+        }
+    }
+
+    /// Returns the current suspicion level of a tracked node.
+    pub fn phi(&self, node: &N) -> Option<f64> {
+        let now = self.clock.now();
+        self.histories
+            .get(node)
+            .map(|history| self.phi_at(history, now))
+    }
+
+    /// Computes the suspicion level of a node with the given history at the given time. Phi is 0
+    /// until at least one interval has been observed.
+    fn phi_at(&self, history: &History, now: Instant) -> f64 {
+        let n = history.intervals.len();
+        if n == 0 {
+            return 0.0;
+        }
+        let mean = history.intervals.iter().sum::<f64>() / n as f64;
+        let variance = history
+            .intervals
+            .iter()
+            .map(|interval| (interval - mean).powi(2))
+            .sum::<f64>()
+            / n as f64;
+        let std_deviation = variance.sqrt().max(self.min_std_deviation);
+        let elapsed = now.saturating_duration_since(history.last).as_secs_f64();
+        // A logistic approximation of the cumulative distribution function of the normal
+        // distribution.
+        let y = (elapsed - mean) / std_deviation;
+        let e = (-y * (1.5976 + 0.070_566 * y * y)).exp();
+        let p_later = if elapsed > mean {
+            e / (1.0 + e)
+        } else {
+            1.0 - 1.0 / (1.0 + e)
+        };
+        -p_later.max(f64::MIN_POSITIVE).log10()
+    }
+}
+
+impl<N: NodeId, C: Clock> FailureDetector<N> for PhiAccrualDetector<N, C> {
+    /// Declares failed and stops tracking every node whose phi exceeds the threshold.
+    fn poll_failures(&mut self) -> Result<(), Error> {
+        let now = self.clock.now();
+        let failed: Vec<N> = self
+            .histories
+            .iter()
+            .filter(|(_, history)| self.phi_at(history, now) > self.threshold)
+            .map(|(node, _)| node.clone())
+            .collect();
+        for node in failed {
+            self.histories.remove(&node);
+            self.failures.push(node);
+        }
+        Ok(())
+    }
+
+    fn dequeue_failures(&mut self) -> Vec<N> {
+        self.failures.drain(..).collect()
+    }
+
+    /// Treats the activity as a heartbeat.
+    fn record_activity(&mut self, node: &N) {
+        let now = self.clock.now();
+        self.heartbeat(node.clone(), now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{PhiAccrualDetector, DEFAULT_THRESHOLD};
+    use crate::failure_detector::{Clock, FailureDetector, ManualClock};
+
+    #[test]
+    fn silent_node_crosses_threshold() {
+        let clock = ManualClock::new();
+        let mut detector = PhiAccrualDetector::new(DEFAULT_THRESHOLD, 10, clock.clone());
+        for _ in 0..10 {
+            detector.heartbeat(0u64, clock.now());
+            detector.heartbeat(1u64, clock.now());
+            clock.advance(Duration::from_secs(1));
+        }
+        // Node 1 goes silent while node 0 keeps heartbeating.
+        detector.heartbeat(0, clock.now());
+        detector.poll_failures().unwrap();
+        assert!(detector.dequeue_failures().is_empty());
+        for _ in 0..3 {
+            clock.advance(Duration::from_secs(1));
+            detector.heartbeat(0, clock.now());
+        }
+        assert!(detector.phi(&0).unwrap() < DEFAULT_THRESHOLD);
+        assert!(detector.phi(&1).unwrap() > DEFAULT_THRESHOLD);
+        detector.poll_failures().unwrap();
+        assert_eq!(detector.dequeue_failures(), vec![1]);
+        // A failed node is no longer tracked.
+        assert!(detector.phi(&1).is_none());
+    }
+}
@@ -0,0 +1,17 @@
+//! Event signatures.
+//!
+//! A node signs the events it creates with its `Signer`. The events received from a remote node
+//! are checked with the `Verifier` of that node. A signature covers all fields of an event except
+//! the signature itself, as given by `Event::signed_bytes`.
+
+/// Signs the events of the local node.
+pub trait Signer {
+    /// Signs the given bytes.
+    fn sign(&self, bytes: &[u8]) -> Vec<u8>;
+}
+
+/// Verifies the signatures of the events of a remote node.
+pub trait Verifier {
+    /// Checks that the signature over the given bytes was made by the node.
+    fn verify(&self, bytes: &[u8], signature: &[u8]) -> bool;
+}
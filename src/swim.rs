@@ -0,0 +1,281 @@
+//! A SWIM-style ping/ack failure detector.
+//!
+//! In every protocol period the detector picks a random member and pings it directly. If no ack
+//! arrives within the timeout, it asks `k` other members to ping the target on its behalf. If no
+//! ack, direct or indirect, arrives within another timeout, the target is declared failed.
+//!
+//! The detector doesn't do any networking itself: the probes it wants sent are collected with
+//! `take_probes` and the acks received are fed back with `record_ack`.
+
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::seq::IteratorRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::failure_detector::{Clock, Error, FailureDetector, SystemClock};
+use crate::graph::NodeId;
+
+/// The default number of members asked to ping an unresponsive target.
+pub const DEFAULT_INDIRECT_PROBES: usize = 3;
+
+/// The default time to wait for an ack in each phase of a probe.
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A probe message to be sent by the networking layer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Probe<N> {
+    /// A direct ping of the target.
+    Ping { target: N },
+    /// A request to `via` to ping the target on behalf of this node.
+    PingReq { via: N, target: N },
+}
+
+/// The probe in progress.
+struct PendingProbe<N> {
+    /// The probed member.
+    target: N,
+    /// The start of the current phase of the probe.
+    started: Instant,
+    /// Whether indirect pings have been requested.
+    indirect: bool,
+}
+
+/// A SWIM-style failure detector.
+pub struct SwimDetector<N: NodeId, C: Clock = SystemClock, R: Rng = StdRng> {
+    /// The ID of this node, which is never probed.
+    our_id: N,
+    /// The members to probe.
+    members: BTreeSet<N>,
+    /// The number of members asked to ping an unresponsive target.
+    indirect_probes: usize,
+    /// The time to wait for an ack in each phase of a probe.
+    timeout: Duration,
+    /// The probe in progress, if any.
+    pending: Option<PendingProbe<N>>,
+    /// The probes waiting to be sent.
+    probes: Vec<Probe<N>>,
+    /// The queue of unhandled node failures.
+    failures: Vec<N>,
+    /// The source of the current time.
+    clock: C,
+    /// The source of randomness for target selection.
+    rng: R,
+}
+
+impl<N: NodeId> SwimDetector<N> {
+    /// Creates a new SWIM detector with the default configuration, the system clock and an
+    /// entropy-seeded random number generator.
+    pub fn with_defaults(our_id: N) -> Self {
+        SwimDetector::new(
+            our_id,
+            DEFAULT_INDIRECT_PROBES,
+            DEFAULT_PROBE_TIMEOUT,
+            SystemClock,
+            StdRng::from_entropy(),
+        )
+    }
+}
+
+impl<N: NodeId, C: Clock, R: Rng> SwimDetector<N, C, R> {
+    /// Creates a new SWIM detector.
+    pub fn new(our_id: N, indirect_probes: usize, timeout: Duration, clock: C, rng: R) -> Self {
+        SwimDetector {
+            our_id,
+            members: BTreeSet::new(),
+            indirect_probes,
+            timeout,
+            pending: None,
+            probes: Vec::new(),
+            failures: Vec::new(),
+            clock,
+            rng,
+        }
+    }
+
+    /// Starts probing a member.
+    pub fn add_member(&mut self, node: N) {
+        if node != self.our_id {
+            self.members.insert(node);
+        }
+    }
+
+    /// Stops probing a member.
+    pub fn remove_member(&mut self, node: &N) {
+        self.members.remove(node);
+        if self.pending.as_ref().map(|p| &p.target) == Some(node) {
+            self.pending = None;
+        }
+    }
+
+    /// Returns the probes to be sent since the previous call.
+    pub fn take_probes(&mut self) -> Vec<Probe<N>> {
+        self.probes.drain(..).collect()
+    }
+
+    /// Records an ack from a node, received either directly or through another member. An ack
+    /// from the probed member completes the probe.
+    pub fn record_ack(&mut self, node: &N) {
+        if self.pending.as_ref().map(|p| &p.target) == Some(node) {
+            self.pending = None;
+        }
+    }
+}
+
+impl<N: NodeId, C: Clock, R: Rng> FailureDetector<N> for SwimDetector<N, C, R> {
+    /// Advances the probe in progress or starts a new one.
+    fn poll_failures(&mut self) -> Result<(), Error> {
+        let now = self.clock.now();
+        match self.pending.take() {
+            None => {
+                let our_id = &self.our_id;
+                let target = self
+                    .members
+                    .iter()
+                    .filter(|node| *node != our_id)
+                    .choose(&mut self.rng)
+                    .cloned();
+                if let Some(target) = target {
+                    self.probes.push(Probe::Ping {
+                        target: target.clone(),
+                    });
+                    self.pending = Some(PendingProbe {
+                        target,
+                        started: now,
+                        indirect: false,
+                    });
+                }
+            }
+            Some(pending) if now.saturating_duration_since(pending.started) < self.timeout => {
+                self.pending = Some(pending);
+            }
+            Some(PendingProbe {
+                target,
+                indirect: false,
+                ..
+            }) => {
+                let our_id = &self.our_id;
+                let helpers = self
+                    .members
+                    .iter()
+                    .filter(|node| *node != our_id && **node != target)
+                    .choose_multiple(&mut self.rng, self.indirect_probes);
+                for via in helpers {
+                    self.probes.push(Probe::PingReq {
+                        via: via.clone(),
+                        target: target.clone(),
+                    });
+                }
+                self.pending = Some(PendingProbe {
+                    target,
+                    started: now,
+                    indirect: true,
+                });
+            }
+            Some(PendingProbe { target, .. }) => {
+                self.members.remove(&target);
+                self.failures.push(target);
+            }
+        }
+        Ok(())
+    }
+
+    fn dequeue_failures(&mut self) -> Vec<N> {
+        self.failures.drain(..).collect()
+    }
+
+    /// Treats the activity as an ack.
+    fn record_activity(&mut self, node: &N) {
+        self.record_ack(node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::{Probe, SwimDetector};
+    use crate::failure_detector::{FailureDetector, ManualClock};
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn detector(clock: &ManualClock) -> SwimDetector<u64, ManualClock> {
+        let mut detector =
+            SwimDetector::new(0, 2, TIMEOUT, clock.clone(), StdRng::seed_from_u64(0));
+        for node in 0..4 {
+            detector.add_member(node);
+        }
+        detector
+    }
+
+    /// Starts a probe and returns its target.
+    fn ping(detector: &mut SwimDetector<u64, ManualClock>) -> u64 {
+        detector.poll_failures().unwrap();
+        match detector.take_probes().as_slice() {
+            [Probe::Ping { target }] => *target,
+            probes => panic!("unexpected probes {:?}", probes),
+        }
+    }
+
+    #[test]
+    fn direct_ack_completes_probe() {
+        let clock = ManualClock::new();
+        let mut detector = detector(&clock);
+        for _ in 0..20 {
+            let target = ping(&mut detector);
+            assert_ne!(target, 0);
+            detector.record_ack(&target);
+            clock.advance(TIMEOUT);
+        }
+        assert!(detector.dequeue_failures().is_empty());
+    }
+
+    #[test]
+    fn indirect_ack_rescues_target() {
+        let clock = ManualClock::new();
+        let mut detector = detector(&clock);
+        let target = ping(&mut detector);
+        clock.advance(TIMEOUT);
+        detector.poll_failures().unwrap();
+        let probes = detector.take_probes();
+        assert_eq!(probes.len(), 2);
+        for probe in probes {
+            match probe {
+                Probe::PingReq { via, target: t } => {
+                    assert_eq!(t, target);
+                    assert!(via != 0 && via != target);
+                }
+                probe => panic!("unexpected probe {:?}", probe),
+            }
+        }
+        detector.record_ack(&target);
+        clock.advance(TIMEOUT);
+        detector.poll_failures().unwrap();
+        assert!(detector.dequeue_failures().is_empty());
+        // The next poll starts a new probe.
+        assert_eq!(detector.take_probes().len(), 1);
+    }
+
+    #[test]
+    fn unresponsive_target_fails() {
+        let clock = ManualClock::new();
+        let mut detector = detector(&clock);
+        let target = ping(&mut detector);
+        clock.advance(TIMEOUT);
+        detector.poll_failures().unwrap();
+        clock.advance(TIMEOUT);
+        detector.poll_failures().unwrap();
+        assert_eq!(detector.dequeue_failures(), vec![target]);
+        detector.take_probes();
+        // A failed member is no longer probed.
+        for _ in 0..20 {
+            let next = ping(&mut detector);
+            assert_ne!(next, target);
+            detector.record_ack(&next);
+        }
+    }
+}